@@ -0,0 +1,141 @@
+// times a few small programs that stress the interpreter, run with `cargo bench`
+
+use lox_interpreter::interpreter::Interpreter;
+use lox_interpreter::parser::Parser;
+use lox_interpreter::resolver::Resolver;
+use lox_interpreter::scanner::Scanner;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// counts allocations, so front end changes can be compared by more than their timings
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// the loop body is big enough that copying it on each iteration would show up in the timings
+const COUNTING_LOOP: &str = "
+var sum = 0;
+for (var i = 0; i < 1000000; i = i + 1) {
+    var a = i * 2;
+    var b = a - i;
+    if (b == i) {
+        sum = sum + 1;
+    } else {
+        sum = sum - 1;
+    }
+}
+";
+
+// over a million calls, so any per call copying of the function body adds up
+const FIBONACCI: &str = "
+fun fib(n) {
+    if (n < 2) return n;
+    return fib(n - 1) + fib(n - 2);
+}
+fib(30);
+";
+
+// nothing but local variable reads and writes, several scopes deep
+const NESTED_LOOPS: &str = "
+{
+    var total = 0;
+    for (var i = 0; i < 300; i = i + 1) {
+        for (var j = 0; j < 300; j = j + 1) {
+            var product = i * j;
+            if (product > total) total = total + 1;
+        }
+    }
+}
+";
+
+// every iteration looks up a native and two globals from inside a function
+const NATIVE_CALLS: &str = "
+var total = 0;
+var step = 1;
+fun work() {
+    for (var i = 0; i < 300000; i = i + 1) {
+        total = total + abs(step) + floor(step);
+    }
+}
+work();
+";
+
+const RUNS: u32 = 5;
+
+fn run_once(source: &str) -> Duration {
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens().unwrap().clone();
+    let stmts = Parser::new(tokens).parse().unwrap();
+
+    let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+    Resolver::new(&mut interpreter).resolve(&stmts).unwrap();
+
+    let start = Instant::now();
+    interpreter.interpret(stmts).unwrap();
+    start.elapsed()
+}
+
+// scanning, parsing and resolving only, for front end changes
+fn parse_once(source: &str) -> Duration {
+    let start = Instant::now();
+    let mut scanner = Scanner::new(source.to_string());
+    let tokens = scanner.scan_tokens().unwrap().clone();
+    let stmts = Parser::new(tokens).parse().unwrap();
+    let mut interpreter = Interpreter::new();
+    Resolver::new(&mut interpreter).resolve(&stmts).unwrap();
+    start.elapsed()
+}
+
+// a long file of small classes and functions, so names are copied a lot while parsing
+fn parse_heavy_source() -> String {
+    (0..2000)
+        .map(|i| {
+            format!(
+                "class Shape{i} {{ init(width, height) {{ this.width = width; this.height = height; }} \
+                 area() {{ return this.width * this.height; }} }}\n\
+                 fun scaled{i}(shape, factor) {{ var w = shape.width * factor; var h = shape.height * factor; \
+                 return Shape{i}(w, h).area(); }}\n"
+            )
+        })
+        .collect()
+}
+
+fn bench(name: &str, source: &str) {
+    report(name, (0..RUNS).map(|_| run_once(source)).collect());
+}
+
+fn report(name: &str, timings: Vec<Duration>) {
+    let best = timings.iter().min().unwrap();
+    let mean = timings.iter().sum::<Duration>() / RUNS;
+
+    println!("{name}: best {best:?}, mean {mean:?} over {RUNS} runs");
+}
+
+fn main() {
+    bench("counting loop", COUNTING_LOOP);
+    bench("fib(30)", FIBONACCI);
+    bench("native calls", NATIVE_CALLS);
+    bench("nested loops", NESTED_LOOPS);
+
+    let source = parse_heavy_source();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    report("parse", (0..RUNS).map(|_| parse_once(&source)).collect());
+    let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - before) / RUNS as usize;
+    println!("parse: {allocations} allocations per run");
+}
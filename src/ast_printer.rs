@@ -0,0 +1,254 @@
+use crate::parser::*;
+use crate::token::Literal;
+
+// renders the syntax tree as lisp style s-expressions, e.g. (+ 1 (* 2 3))
+// every operator is explicitly parenthesised, which makes it handy for checking precedence
+pub fn print_stmts(stmts: &[Stmt]) -> String {
+    stmts
+        .iter()
+        .map(print_stmt)
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub fn print_stmt(stmt: &Stmt) -> String {
+    match stmt {
+        Stmt::ExprStmt(expr) => format!("(; {})", print_expr(expr)),
+        Stmt::PrintStmt(expr) => format!("(print {})", print_expr(expr)),
+        Stmt::VarDeclStmt(var) => {
+            format!("(var {} {})", var.name.lexeme, print_expr(&var.initialiser))
+        }
+        Stmt::MultiVarDeclStmt(vars) => {
+            let declarations: Vec<String> = vars
+                .iter()
+                .map(|var| format!("({} {})", var.name.lexeme, print_expr(&var.initialiser)))
+                .collect();
+            format!("(var {})", declarations.join(" "))
+        }
+        Stmt::FuncDeclStmt(func) => print_function("fun", func),
+        Stmt::ClassDeclStmt(class) => {
+            let mut out = format!("(class {}", class.name.lexeme);
+            if let Some(ref superclass) = class.superclass {
+                out.push_str(&format!(" < {}", superclass.name.lexeme));
+            }
+            for method in &class.methods {
+                out.push(' ');
+                let keyword = if method.getter { "getter" } else { "method" };
+                out.push_str(&print_function(keyword, method));
+            }
+            out.push(')');
+            out
+        }
+        Stmt::BlockStmt(block) => parenthesise_stmts("block", &block.statements),
+        Stmt::IfStmt(ifstmt) => {
+            let mut out = format!(
+                "(if {} {}",
+                print_expr(&ifstmt.condition),
+                print_stmt(&ifstmt.then_branch)
+            );
+            // the parser fills in a missing else branch with a nil expression statement
+            if ifstmt.else_branch != Stmt::ExprStmt(Expr::LitExpr(Literal::Null)) {
+                out.push(' ');
+                out.push_str(&print_stmt(&ifstmt.else_branch));
+            }
+            out.push(')');
+            out
+        }
+        Stmt::WhileStmt(whilestmt) => match whilestmt.increment {
+            Some(ref increment) => format!(
+                "(while {} {} {})",
+                print_expr(&whilestmt.condition),
+                print_stmt(&whilestmt.body),
+                print_expr(increment)
+            ),
+            None => format!(
+                "(while {} {})",
+                print_expr(&whilestmt.condition),
+                print_stmt(&whilestmt.body)
+            ),
+        },
+        Stmt::DoWhileStmt(dowhile) => format!(
+            "(do {} {})",
+            print_stmt(&dowhile.body),
+            print_expr(&dowhile.condition)
+        ),
+        Stmt::SwitchStmt(switch) => {
+            let mut out = format!("(switch {}", print_expr(&switch.discriminant));
+            for (case, statements) in &switch.cases {
+                out.push(' ');
+                out.push_str(&parenthesise_stmts(
+                    &format!("case {}", print_expr(case)),
+                    statements,
+                ));
+            }
+            if let Some(ref statements) = switch.default {
+                out.push(' ');
+                out.push_str(&parenthesise_stmts("default", statements));
+            }
+            out.push(')');
+            out
+        }
+        Stmt::ForEachStmt(foreach) => format!(
+            "(for {} {} {})",
+            foreach.variable.lexeme,
+            print_expr(&foreach.iterable),
+            print_stmt(&foreach.body)
+        ),
+        // for loops are desugared into while loops by the parser
+        Stmt::ForStmt(_) => "(for)".to_string(),
+        Stmt::ReturnStmt(ret) => format!("(return {})", print_expr(&ret.value)),
+        Stmt::ImportStmt(import) => format!("(import {})", import.path.lexeme),
+        Stmt::BreakStmt(_) => "(break)".to_string(),
+        Stmt::ContinueStmt(_) => "(continue)".to_string(),
+    }
+}
+
+pub fn print_expr(expr: &Expr) -> String {
+    match expr {
+        Expr::AssignExpr(a) => format!("(= {} {})", a.name.lexeme, print_expr(&a.value)),
+        Expr::BinaryExpr(b) => parenthesise(&b.operator.lexeme, &[&b.left, &b.right]),
+        Expr::LogicExpr(l) => parenthesise(&l.operator.lexeme, &[&l.left, &l.right]),
+        Expr::UnaryExpr(u) => parenthesise(&u.operator.lexeme, &[&u.right]),
+        Expr::GroupingExpr(g) => parenthesise("group", &[&g.expression]),
+        Expr::CommaExpr(c) => parenthesise(",", &c.expressions.iter().collect::<Vec<_>>()),
+        Expr::TernaryExpr(t) => parenthesise("?:", &[&t.condition, &t.then_branch, &t.else_branch]),
+        Expr::CallExpr(c) => {
+            let mut parts = vec![&c.callee];
+            if let Some(ref args) = c.arguments {
+                parts.extend(args);
+            }
+            parenthesise("call", &parts)
+        }
+        Expr::LambdaExpr(func) => print_function("fun", func),
+        Expr::GetExpr(g) => format!("(. {} {})", print_expr(&g.object), g.name.lexeme),
+        Expr::SetExpr(s) => format!(
+            "(= (. {} {}) {})",
+            print_expr(&s.object),
+            s.name.lexeme,
+            print_expr(&s.value)
+        ),
+        Expr::SuperExpr(s) => format!("(super {})", s.method.lexeme),
+        Expr::ThisExpr(_) => "this".to_string(),
+        Expr::VarExpr(v) => v.name.lexeme.to_string(),
+        Expr::ListExpr(list) => parenthesise("list", &list.elements.iter().collect::<Vec<_>>()),
+        Expr::MapExpr(map) => {
+            let mut out = "(map".to_string();
+            for (key, value) in &map.entries {
+                out.push_str(&format!(" ({} {})", print_expr(key), print_expr(value)));
+            }
+            out.push(')');
+            out
+        }
+        Expr::IndexExpr(i) => parenthesise("[]", &[&i.object, &i.index]),
+        Expr::IndexSetExpr(i) => format!(
+            "(= {} {})",
+            parenthesise("[]", &[&i.object, &i.index]),
+            print_expr(&i.value)
+        ),
+        // strings are quoted so they can't be mistaken for identifiers
+        Expr::LitExpr(l) => l.debug_string(),
+    }
+}
+
+fn print_function(keyword: &str, func: &FuncDecl) -> String {
+    let mut params: Vec<String> = func
+        .params
+        .iter()
+        .map(|p| match p.default {
+            Some(ref default) => format!("(= {} {})", p.name.lexeme, print_expr(default)),
+            None => p.name.lexeme.to_string(),
+        })
+        .collect();
+    if let Some(ref rest) = func.rest {
+        params.push(format!("...{}", rest.lexeme));
+    }
+    let mut out = format!("({keyword}");
+    if !func.name.lexeme.is_empty() {
+        out.push(' ');
+        out.push_str(&func.name.lexeme);
+    }
+    // getters have no parameter list
+    if !func.getter {
+        out.push_str(&format!(" ({})", params.join(" ")));
+    }
+    for stmt in func.body.iter() {
+        out.push(' ');
+        out.push_str(&print_stmt(stmt));
+    }
+    out.push(')');
+    out
+}
+
+fn parenthesise(name: &str, exprs: &[&Expr]) -> String {
+    let mut out = format!("({name}");
+    for expr in exprs {
+        out.push(' ');
+        out.push_str(&print_expr(expr));
+    }
+    out.push(')');
+    out
+}
+
+fn parenthesise_stmts(name: &str, stmts: &[Stmt]) -> String {
+    let mut out = format!("({name}");
+    for stmt in stmts {
+        out.push(' ');
+        out.push_str(&print_stmt(stmt));
+    }
+    out.push(')');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    fn print(source: &str) -> String {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap().clone();
+        print_stmts(&Parser::new(tokens).parse().unwrap())
+    }
+
+    #[test]
+    fn operators_are_parenthesised_by_precedence() {
+        assert_eq!(print("1 + 2 * 3;"), "(; (+ 1 (* 2 3)))");
+        assert_eq!(print("(1 + 2) * 3;"), "(; (* (group (+ 1 2)) 3))");
+        assert_eq!(print("-a - -b;"), "(; (- (- a) (- b)))");
+        assert_eq!(print("a or b and c;"), "(; (or a (and b c)))");
+        assert_eq!(print("a ? b : c ? d : e;"), "(; (?: a b (?: c d e)))");
+        assert_eq!(print("a = b = 1;"), "(; (= a (= b 1)))");
+    }
+
+    #[test]
+    fn statements_print_one_per_line() {
+        assert_eq!(
+            print("var s = \"hi\"; if (s) print s; else { print nil; }"),
+            "(var s \"hi\")\n(if s (print s) (block (print nil)))"
+        );
+        assert_eq!(
+            print("for (var i = 0; i < 2; i = i + 1) print i;"),
+            "(block (var i 0) (while (< i 2) (print i) (= i (+ i 1))))"
+        );
+    }
+
+    #[test]
+    fn functions_classes_and_collections() {
+        assert_eq!(
+            print("fun add(a, b) { return a + b; }"),
+            "(fun add (a b) (return (+ a b)))"
+        );
+        assert_eq!(
+            print("class B < A { m() { return super.m(this.x); } }"),
+            "(class B < A (method m () (return (call (super m) (. this x)))))"
+        );
+        assert_eq!(
+            print("class C { area { return this.r; } }"),
+            "(class C (getter area (return (. this r))))"
+        );
+        assert_eq!(
+            print("xs[0] = {\"k\": [1, 2]};"),
+            "(; (= ([] xs 0) (map (\"k\" (list 1 2)))))"
+        );
+    }
+}
@@ -1,86 +1,760 @@
 use crate::environment::Environment;
 use crate::error::*;
 use crate::parser::FuncDecl;
+use std::any::Any;
 use std::cell::RefCell;
-use std::fmt::Display;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display};
 use std::rc::Rc;
-use std::time::SystemTime;
-use std::time::UNIX_EPOCH;
 
 use crate::interpreter::Interpreter;
-use crate::token::Literal;
+use crate::scanner::Scanner;
+use crate::token::{Literal, Token, TokenType};
 
-pub trait Callable {
+// functions, natives and host functions are all held as Literal::Callable and called through this
+pub trait Callable: Debug + Display {
     fn arity(&self) -> i32;
+    // fewer than arity when trailing parameters have defaults
+    fn required_arity(&self) -> i32 {
+        self.arity()
+    }
+    // takes any number of arguments past arity, like a function with a rest parameter
+    fn is_variadic(&self) -> bool {
+        false
+    }
     fn call(
         &self,
         interpreter: &mut Interpreter,
         arguments: Vec<Literal>,
+        paren: &Token,
     ) -> Result<Literal, RuntimeBreak>;
+    // what the type native reports for it
+    fn type_name(&self) -> &'static str {
+        "native function"
+    }
+    // lets a callable be compared with one whose concrete type isn't known, see same_callable
+    fn as_any(&self) -> &dyn Any;
+    fn equals(&self, other: &dyn Callable) -> bool;
 }
 
-#[derive(Debug, PartialEq, Clone)]
+impl PartialEq for dyn Callable {
+    fn eq(&self, other: &Self) -> bool {
+        self.equals(other)
+    }
+}
+
+// callables of different kinds are never equal, otherwise each kind compares in its own way
+fn same_callable<T: PartialEq + 'static>(this: &T, other: &dyn Callable) -> bool {
+    other
+        .as_any()
+        .downcast_ref::<T>()
+        .is_some_and(|other| this == other)
+}
+
+#[derive(Clone)]
 pub struct Function {
-    declaration: Box<FuncDecl>,
+    declaration: Rc<FuncDecl>,
+    closure: Rc<RefCell<Environment>>,
+    // a class's init method, which returns the instance however it finishes
+    is_initializer: bool,
+    // the imported file it was declared in, None for the program itself
+    file: Option<Rc<SourceFile>>,
 }
 
 impl Function {
-    pub fn new(declaration: FuncDecl) -> Self {
+    pub fn new(
+        declaration: FuncDecl,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+        file: Option<Rc<SourceFile>>,
+    ) -> Self {
         Self {
-            declaration: Box::new(declaration),
+            declaration: Rc::new(declaration),
+            closure,
+            is_initializer,
+            file,
+        }
+    }
+
+    pub fn is_getter(&self) -> bool {
+        self.declaration.getter
+    }
+
+    pub fn name_token(&self) -> &Token {
+        &self.declaration.name
+    }
+
+    // lambdas have an empty name token
+    pub fn name(&self) -> &str {
+        if self.declaration.name.lexeme.is_empty() {
+            "anonymous"
+        } else {
+            &self.declaration.name.lexeme
+        }
+    }
+
+    // parameters missing from the call take their defaults, evaluated in order in the new environment
+    fn bind_parameters(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Literal>,
+    ) -> Result<Rc<RefCell<Environment>>, RuntimeBreak> {
+        let env = Rc::new(RefCell::new(Environment::new(Some(self.closure.clone()))));
+        let mut arguments = arguments.into_iter();
+        for param in &self.declaration.params {
+            let value = match (arguments.next(), &param.default) {
+                (Some(value), _) => value,
+                (None, Some(default)) => interpreter.evaluate_in(default, env.clone())?,
+                // the arity has already been checked
+                (None, None) => Literal::Null,
+            };
+            env.borrow_mut().define(param.name.lexeme.clone(), value);
+        }
+        if let Some(ref rest) = self.declaration.rest {
+            let rest_list = Literal::List(Rc::new(RefCell::new(arguments.collect())));
+            env.borrow_mut().define(rest.lexeme.clone(), rest_list);
+        }
+        Ok(env)
+    }
+
+    // wraps the closure in an environment where "this" is the given instance
+    pub fn bind(&self, instance: Rc<RefCell<LoxInstance>>) -> Function {
+        let env = Rc::new(RefCell::new(Environment::new(Some(self.closure.clone()))));
+        env.borrow_mut()
+            .define("this".into(), Literal::Instance(instance));
+        Function {
+            declaration: self.declaration.clone(),
+            closure: env,
+            is_initializer: self.is_initializer,
+            file: self.file.clone(),
         }
     }
 }
 
+// the closure is left out as it can contain the function itself
+impl Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Function")
+            .field("declaration", &self.declaration)
+            .finish_non_exhaustive()
+    }
+}
+
+// functions are only equal to themselves, as in jlox, so each closure and each bound method is distinct
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.declaration, &other.declaration)
+            && Rc::ptr_eq(&self.closure, &other.closure)
+    }
+}
+
 impl Callable for Function {
     fn call(
         &self,
         interpreter: &mut Interpreter,
         arguments: Vec<Literal>,
+        paren: &Token,
     ) -> Result<Literal, RuntimeBreak> {
-        let env = Rc::new(RefCell::new(Environment::new(Some(
-            interpreter.globals.clone(),
-        ))));
-        for param in self.declaration.params.iter().enumerate() {
-            env.borrow_mut().define(
-                param.1.lexeme.clone(),
-                arguments.get(param.0).unwrap().clone(),
-            );
+        interpreter.check_step_limit(paren)?;
+        if interpreter.call_stack.len() >= interpreter.max_call_depth {
+            return Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                paren.clone(),
+                "Stack overflow.".to_string(),
+            )));
         }
 
-        let block_result = interpreter.execute_block(self.declaration.body.clone(), env);
+        interpreter.call_stack.push(CallFrame {
+            function: self.name().to_string(),
+            line: paren.line,
+        });
+        // lambdas and classes declared in the body belong to the same file as the function
+        let caller_file = std::mem::replace(&mut interpreter.file, self.file.clone());
+        // defaults are evaluated inside the call, so they show up in its stack trace
+        let block_result = self
+            .bind_parameters(interpreter, arguments)
+            .and_then(|env| interpreter.execute_block(&self.declaration.body, env));
+        interpreter.file = caller_file;
 
-        if let Err(RuntimeBreak::ReturnBreak(re)) = block_result {
+        let result = if let (true, Ok(_) | Err(RuntimeBreak::ReturnBreak(_))) =
+            (self.is_initializer, &block_result)
+        {
+            // bound methods define "this" in the environment they close over
+            let this = Token::new(TokenType::This, "this".to_string(), Literal::Null, 0, 0);
+            self.closure
+                .borrow()
+                .get_at(0, 0, &this)
+                .map_err(RuntimeBreak::RuntimeErrorBreak)
+        } else if let Err(RuntimeBreak::ReturnBreak(re)) = block_result {
             Ok(re.value)
-        } else if let Err(RuntimeBreak::RuntimeErrorBreak(re)) = block_result {
+        } else if let Err(RuntimeBreak::RuntimeErrorBreak(mut re)) = block_result {
+            // only the innermost call records the trace, as it still has every frame on the stack
+            // it's also the one whose body the error happened in, unless an import inside it already said where
+            if re.trace.is_empty() {
+                re.trace = interpreter.call_stack.iter().rev().cloned().collect();
+                if re.file.is_none() {
+                    re.file = self.file.clone();
+                }
+            }
             Err(RuntimeBreak::RuntimeErrorBreak(re))
+        } else if let Err(RuntimeBreak::ExitBreak(code)) = block_result {
+            Err(RuntimeBreak::ExitBreak(code))
         } else {
             Ok(Literal::Null)
-        }
+        };
+
+        interpreter.call_stack.pop();
+        result
     }
 
     fn arity(&self) -> i32 {
         self.declaration.params.len() as i32
     }
+
+    fn required_arity(&self) -> i32 {
+        let defaults = self
+            .declaration
+            .params
+            .iter()
+            .filter(|p| p.default.is_some());
+        self.arity() - defaults.count() as i32
+    }
+
+    fn is_variadic(&self) -> bool {
+        self.declaration.rest.is_some()
+    }
+
+    fn type_name(&self) -> &'static str {
+        "function"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn equals(&self, other: &dyn Callable) -> bool {
+        same_callable(self, other)
+    }
 }
 
 impl Display for Function {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "<fn {}>", self.declaration.name)
+        write!(f, "<fn {}>", self.name())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LoxClass {
+    pub name: Rc<str>,
+    superclass: Option<Rc<LoxClass>>,
+    methods: HashMap<Rc<str>, Function>,
+}
+
+impl LoxClass {
+    pub fn new(
+        name: Rc<str>,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<Rc<str>, Function>,
+    ) -> Self {
+        Self {
+            name,
+            superclass,
+            methods,
+        }
+    }
+
+    // inherited from the superclass when the class doesn't declare its own
+    fn initializer(&self) -> Option<&Function> {
+        self.find_method("init")
+            .filter(|method| method.is_initializer)
+    }
+
+    // a class counts as a subclass of itself
+    pub fn is_subclass_of(&self, class: &LoxClass) -> bool {
+        std::ptr::eq(self, class)
+            || self
+                .superclass
+                .as_ref()
+                .is_some_and(|superclass| superclass.is_subclass_of(class))
+    }
+
+    // walks up the inheritance chain until the method is found
+    pub fn find_method(&self, name: &str) -> Option<&Function> {
+        self.methods.get(name).or_else(|| {
+            self.superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name))
+        })
+    }
+}
+
+// classes are shared between every instance they construct, so calling one needs the Rc
+// the arguments are passed on to init, which takes its parameters from the class's arity
+impl Callable for Rc<LoxClass> {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Literal>,
+        paren: &Token,
+    ) -> Result<Literal, RuntimeBreak> {
+        let instance = Rc::new(RefCell::new(LoxInstance::new(Rc::clone(self))));
+        if let Some(init) = self.initializer() {
+            init.bind(Rc::clone(&instance))
+                .call(interpreter, arguments, paren)?;
+        }
+        Ok(Literal::Instance(instance))
+    }
+
+    fn arity(&self) -> i32 {
+        self.initializer().map_or(0, Callable::arity)
+    }
+
+    fn required_arity(&self) -> i32 {
+        self.initializer().map_or(0, Callable::required_arity)
+    }
+
+    fn is_variadic(&self) -> bool {
+        self.initializer().is_some_and(Callable::is_variadic)
+    }
+
+    fn type_name(&self) -> &'static str {
+        "class"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn equals(&self, other: &dyn Callable) -> bool {
+        same_callable(self, other)
+    }
+}
+
+impl Display for LoxClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct LoxInstance {
+    class: Rc<LoxClass>,
+    fields: HashMap<Rc<str>, Literal>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+
+    // the getter a read of the property runs, unless a field of the same name shadows it
+    pub fn getter(instance: &Rc<RefCell<LoxInstance>>, name: &Token) -> Option<Function> {
+        let this = instance.borrow();
+        if this.fields.contains_key(&name.lexeme) {
+            return None;
+        }
+        this.class
+            .find_method(&name.lexeme)
+            .filter(|method| method.is_getter())
+            .map(|method| method.bind(Rc::clone(instance)))
+    }
+
+    // a to_string method declared by the class, or inherited, which print and str use instead of the default format
+    // one that needs arguments can't be called that way, so is left alone
+    pub fn to_string_method(instance: &Rc<RefCell<LoxInstance>>) -> Option<Function> {
+        instance
+            .borrow()
+            .class
+            .find_method("to_string")
+            .filter(|method| method.required_arity() == 0)
+            .map(|method| method.bind(Rc::clone(instance)))
+    }
+
+    // fields shadow methods of the same name
+    // takes the shared instance so that methods can be bound to it
+    pub fn get(instance: &Rc<RefCell<LoxInstance>>, name: &Token) -> Result<Literal, RuntimeError> {
+        let this = instance.borrow();
+
+        if let Some(value) = this.fields.get(&name.lexeme) {
+            Ok(value.clone())
+        } else if let Some(method) = this.class.find_method(&name.lexeme) {
+            Ok(Literal::Callable(Rc::new(method.bind(Rc::clone(instance)))))
+        } else {
+            Err(RuntimeError::new(
+                name.clone(),
+                format!("Undefined property '{}'.", name.lexeme),
+            ))
+        }
+    }
+
+    pub fn set(&mut self, name: &Token, value: Literal) {
+        self.fields.insert(name.lexeme.clone(), value);
+    }
+}
+
+// instances are only equal to themselves
+impl PartialEq for LoxInstance {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Display for LoxInstance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<{} instance>", self.class.name)
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum NativeFunction {
     Clock,
+    ClockMillis,
+    Sqrt,
+    Pow,
+    Floor,
+    Ceil,
+    Abs,
+    Min,
+    Max,
+    Clamp,
+    Sin,
+    Cos,
+    Tan,
+    Log,
+    Log10,
+    Exp,
+    Random,
+    RandomInt,
+    Seed,
+    ReadLine,
+    Str,
+    Num,
+    ToNumber,
+    Keys,
+    Substring,
+    Upper,
+    Lower,
+    Trim,
+    Split,
+    Replace,
+    Type,
+    IsInstance,
+    Args,
+    Assert,
+    AssertEq,
+    Exit,
 }
 
 impl NativeFunction {
-    pub fn clock() -> f32 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs_f32()
+    // every native, for defining them in the global scope
+    pub const ALL: [NativeFunction; 36] = [
+        NativeFunction::Clock,
+        NativeFunction::ClockMillis,
+        NativeFunction::Sqrt,
+        NativeFunction::Pow,
+        NativeFunction::Floor,
+        NativeFunction::Ceil,
+        NativeFunction::Abs,
+        NativeFunction::Min,
+        NativeFunction::Max,
+        NativeFunction::Clamp,
+        NativeFunction::Sin,
+        NativeFunction::Cos,
+        NativeFunction::Tan,
+        NativeFunction::Log,
+        NativeFunction::Log10,
+        NativeFunction::Exp,
+        NativeFunction::Random,
+        NativeFunction::RandomInt,
+        NativeFunction::Seed,
+        NativeFunction::ReadLine,
+        NativeFunction::Str,
+        NativeFunction::Num,
+        NativeFunction::ToNumber,
+        NativeFunction::Keys,
+        NativeFunction::Substring,
+        NativeFunction::Upper,
+        NativeFunction::Lower,
+        NativeFunction::Trim,
+        NativeFunction::Split,
+        NativeFunction::Replace,
+        NativeFunction::Type,
+        NativeFunction::IsInstance,
+        NativeFunction::Args,
+        NativeFunction::Assert,
+        NativeFunction::AssertEq,
+        NativeFunction::Exit,
+    ];
+
+    // the global the native is bound to
+    pub fn name(&self) -> &'static str {
+        match self {
+            NativeFunction::Clock => "clock",
+            NativeFunction::ClockMillis => "clock_millis",
+            NativeFunction::Sqrt => "sqrt",
+            NativeFunction::Pow => "pow",
+            NativeFunction::Floor => "floor",
+            NativeFunction::Ceil => "ceil",
+            NativeFunction::Abs => "abs",
+            NativeFunction::Min => "min",
+            NativeFunction::Max => "max",
+            NativeFunction::Clamp => "clamp",
+            NativeFunction::Sin => "sin",
+            NativeFunction::Cos => "cos",
+            NativeFunction::Tan => "tan",
+            NativeFunction::Log => "log",
+            NativeFunction::Log10 => "log10",
+            NativeFunction::Exp => "exp",
+            NativeFunction::Random => "random",
+            NativeFunction::RandomInt => "random_int",
+            NativeFunction::Seed => "seed",
+            NativeFunction::ReadLine => "read_line",
+            NativeFunction::Str => "str",
+            NativeFunction::Num => "num",
+            NativeFunction::ToNumber => "to_number",
+            NativeFunction::Keys => "keys",
+            NativeFunction::Substring => "substring",
+            NativeFunction::Upper => "upper",
+            NativeFunction::Lower => "lower",
+            NativeFunction::Trim => "trim",
+            NativeFunction::Split => "split",
+            NativeFunction::Replace => "replace",
+            NativeFunction::Type => "type",
+            NativeFunction::IsInstance => "is_instance",
+            NativeFunction::Args => "args",
+            NativeFunction::Assert => "assert",
+            NativeFunction::AssertEq => "assert_eq",
+            NativeFunction::Exit => "exit",
+        }
+    }
+
+    // natives that can't observe or affect anything outside the script, the only ones a sandboxed interpreter has
+    pub fn is_safe(&self) -> bool {
+        !matches!(
+            self,
+            NativeFunction::Clock | NativeFunction::ClockMillis | NativeFunction::ReadLine
+        )
+    }
+
+    // NaN in gives NaN out, where f64::min and f64::max would quietly drop it
+    fn min_max(a: f64, b: f64, pick: fn(f64, f64) -> f64) -> f64 {
+        if a.is_nan() || b.is_nan() {
+            f64::NAN
+        } else {
+            pick(a, b)
+        }
+    }
+
+    fn clamp(x: f64, low: f64, high: f64, paren: &Token) -> Result<Literal, RuntimeBreak> {
+        if x.is_nan() || low.is_nan() || high.is_nan() {
+            Ok(Literal::Number(f64::NAN))
+        } else if low > high {
+            Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                paren.clone(),
+                "Lower bound to 'clamp' can't be greater than the upper bound.".to_string(),
+            )))
+        } else {
+            Ok(Literal::Number(x.clamp(low, high)))
+        }
+    }
+
+    // a whole number from low to high, both included
+    fn random_int(
+        interpreter: &mut Interpreter,
+        low: f64,
+        high: f64,
+        paren: &Token,
+    ) -> Result<Literal, RuntimeBreak> {
+        let error = |message: &str| {
+            Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                paren.clone(),
+                message.to_string(),
+            )))
+        };
+
+        if low.fract() != 0.0 || high.fract() != 0.0 {
+            return error("Arguments to 'random_int' must be whole numbers.");
+        } else if low > high {
+            return error("Lower bound to 'random_int' can't be greater than the upper bound.");
+        }
+        let range = ((high - low) as u64).saturating_add(1);
+        let offset = interpreter.next_random() % range;
+        Ok(Literal::Number(low + offset as f64))
+    }
+
+    // the line without its line ending, or nil once the input has run out
+    fn read_line(interpreter: &mut Interpreter, paren: &Token) -> Result<Literal, RuntimeBreak> {
+        let mut line = String::new();
+        match interpreter.read_line(&mut line) {
+            Ok(0) => Ok(Literal::Null),
+            Ok(_) => {
+                let len = line.trim_end_matches(['\n', '\r']).len();
+                line.truncate(len);
+                Ok(Literal::String(line))
+            }
+            Err(err) => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                paren.clone(),
+                format!("Could not read input: {err}"),
+            ))),
+        }
+    }
+
+    // nil for anything that isn't a string holding a number, written any way a script could write it
+    // e.g. "42", " -1.5 ", "0xFF" or "6.022e23", so scripts can check the result rather than fail
+    fn to_number(value: &Literal) -> Literal {
+        let Literal::String(s) = value else {
+            return Literal::Null;
+        };
+        let s = s.trim();
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+
+        let mut scanner = Scanner::new(digits.to_string());
+        match scanner.scan_tokens().map(|tokens| tokens.as_slice()) {
+            // the lexeme check rules out anything the scanner skipped, like the space in "- 1"
+            Ok([number, _eof])
+                if number.ttype == TokenType::Number && &*number.lexeme == digits =>
+            {
+                match number.literal {
+                    Literal::Number(n) if negative => Literal::Number(-n),
+                    ref literal => literal.clone(),
+                }
+            }
+            _ => Literal::Null,
+        }
+    }
+
+    // sorted, as maps have no order of their own
+    fn keys(value: &Literal, paren: &Token) -> Result<Literal, RuntimeBreak> {
+        match value {
+            Literal::Map(map) => {
+                let mut keys: Vec<String> = map.borrow().keys().cloned().collect();
+                keys.sort();
+                let keys = keys.into_iter().map(Literal::String).collect();
+                Ok(Literal::List(Rc::new(RefCell::new(keys))))
+            }
+            _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                paren.clone(),
+                "Argument to 'keys' must be a map.".to_string(),
+            ))),
+        }
+    }
+
+    fn exit(code: f64, paren: &Token) -> Result<Literal, RuntimeBreak> {
+        if code.fract() != 0.0 || code < i32::MIN as f64 || code > i32::MAX as f64 {
+            return Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                paren.clone(),
+                "Argument to 'exit' must be a whole number.".to_string(),
+            )));
+        }
+        Err(RuntimeBreak::ExitBreak(code as i32))
+    }
+
+    // nil when the assertion holds, otherwise a runtime error at the call
+    fn assert(
+        holds: bool,
+        message: impl FnOnce() -> String,
+        paren: &Token,
+    ) -> Result<Literal, RuntimeBreak> {
+        if holds {
+            Ok(Literal::Null)
+        } else {
+            Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                paren.clone(),
+                message(),
+            )))
+        }
+    }
+
+    // anything that isn't an instance is never an instance of a class
+    fn is_instance(arguments: &[Literal], paren: &Token) -> Result<Literal, RuntimeBreak> {
+        match (&arguments[0], &arguments[1]) {
+            (Literal::Instance(instance), Literal::Class(class)) => {
+                Ok(Literal::Bool(instance.borrow().class.is_subclass_of(class)))
+            }
+            (_, Literal::Class(_)) => Ok(Literal::Bool(false)),
+            _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                paren.clone(),
+                "Second argument to 'is_instance' must be a class.".to_string(),
+            ))),
+        }
+    }
+
+    // the characters from start up to but not including end, counted in characters not bytes
+    fn substring(&self, arguments: &[Literal], paren: &Token) -> Result<Literal, RuntimeBreak> {
+        let error = |message: &str| {
+            Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                paren.clone(),
+                message.to_string(),
+            )))
+        };
+
+        let Literal::String(ref s) = arguments[0] else {
+            return error("First argument to 'substring' must be a string.");
+        };
+        let start = self.number_arg(arguments, 1, paren)?;
+        let end = self.number_arg(arguments, 2, paren)?;
+
+        let len = s.chars().count() as f64;
+        if start.fract() != 0.0 || end.fract() != 0.0 {
+            error("Substring bounds must be whole numbers.")
+        } else if start < 0.0 || end > len || start > end {
+            error("Substring bounds out of range.")
+        } else {
+            let slice = s
+                .chars()
+                .skip(start as usize)
+                .take((end - start) as usize)
+                .collect();
+            Ok(Literal::String(slice))
+        }
+    }
+
+    // an empty separator splits the string into its characters
+    fn split(s: &str, separator: &str) -> Literal {
+        let parts: Vec<Literal> = if separator.is_empty() {
+            s.chars().map(|c| Literal::String(c.to_string())).collect()
+        } else {
+            s.split(separator)
+                .map(|part| Literal::String(part.to_string()))
+                .collect()
+        };
+        Literal::List(Rc::new(RefCell::new(parts)))
+    }
+
+    fn string_arg<'a>(
+        &self,
+        arguments: &'a [Literal],
+        index: usize,
+        paren: &Token,
+    ) -> Result<&'a str, RuntimeBreak> {
+        match arguments[index] {
+            Literal::String(ref s) => Ok(s),
+            _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                paren.clone(),
+                format!("Arguments to '{}' must be strings.", self.name()),
+            ))),
+        }
+    }
+
+    // arity has already been checked by the time a native is called
+    fn number_arg(
+        &self,
+        arguments: &[Literal],
+        index: usize,
+        paren: &Token,
+    ) -> Result<f64, RuntimeBreak> {
+        match arguments[index] {
+            Literal::Number(n) => Ok(n),
+            _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                paren.clone(),
+                format!("Arguments to '{}' must be numbers.", self.name()),
+            ))),
+        }
     }
 }
 
@@ -89,14 +763,132 @@ impl Callable for NativeFunction {
         &self,
         interpreter: &mut Interpreter,
         arguments: Vec<Literal>,
+        paren: &Token,
     ) -> Result<Literal, RuntimeBreak> {
+        let number = |index| self.number_arg(&arguments, index, paren);
+        let string = |index| self.string_arg(&arguments, index, paren);
+
         match self {
-            NativeFunction::Clock => Ok(Literal::Number(NativeFunction::clock())),
+            // timed from when the interpreter started, on a clock that never goes backwards
+            NativeFunction::Clock => Ok(Literal::Number(interpreter.elapsed().as_secs_f64())),
+            NativeFunction::ClockMillis => Ok(Literal::Number(
+                interpreter.elapsed().as_secs_f64() * 1000.0,
+            )),
+            NativeFunction::Sqrt => Ok(Literal::Number(number(0)?.sqrt())),
+            NativeFunction::Pow => Ok(Literal::Number(number(0)?.powf(number(1)?))),
+            NativeFunction::Floor => Ok(Literal::Number(number(0)?.floor())),
+            NativeFunction::Ceil => Ok(Literal::Number(number(0)?.ceil())),
+            NativeFunction::Abs => Ok(Literal::Number(number(0)?.abs())),
+            NativeFunction::Min => Ok(Literal::Number(NativeFunction::min_max(
+                number(0)?,
+                number(1)?,
+                f64::min,
+            ))),
+            NativeFunction::Max => Ok(Literal::Number(NativeFunction::min_max(
+                number(0)?,
+                number(1)?,
+                f64::max,
+            ))),
+            NativeFunction::Clamp => {
+                NativeFunction::clamp(number(0)?, number(1)?, number(2)?, paren)
+            }
+            // angles are in radians, and like sqrt these give NaN or Infinity outside their domain
+            NativeFunction::Sin => Ok(Literal::Number(number(0)?.sin())),
+            NativeFunction::Cos => Ok(Literal::Number(number(0)?.cos())),
+            NativeFunction::Tan => Ok(Literal::Number(number(0)?.tan())),
+            NativeFunction::Log => Ok(Literal::Number(number(0)?.ln())),
+            NativeFunction::Log10 => Ok(Literal::Number(number(0)?.log10())),
+            NativeFunction::Exp => Ok(Literal::Number(number(0)?.exp())),
+            // the top 53 bits fill a double's mantissa, giving a uniform value in [0, 1)
+            NativeFunction::Random => Ok(Literal::Number(
+                (interpreter.next_random() >> 11) as f64 / (1u64 << 53) as f64,
+            )),
+            NativeFunction::RandomInt => {
+                NativeFunction::random_int(interpreter, number(0)?, number(1)?, paren)
+            }
+            // whole numbers seed as the same integer an embedder would pass,
+            // anything else by its bits so fractional seeds stay distinct
+            NativeFunction::Seed => {
+                let seed = number(0)?;
+                interpreter.seed(
+                    if seed.fract() == 0.0 && seed >= 0.0 && seed < u64::MAX as f64 {
+                        seed as u64
+                    } else {
+                        seed.to_bits()
+                    },
+                );
+                Ok(Literal::Null)
+            }
+            NativeFunction::ReadLine => NativeFunction::read_line(interpreter, paren),
+            NativeFunction::Str => Ok(Literal::String(interpreter.stringify(&arguments[0])?)),
+            // num is the older name for to_number
+            NativeFunction::Num | NativeFunction::ToNumber => {
+                Ok(NativeFunction::to_number(&arguments[0]))
+            }
+            NativeFunction::Keys => NativeFunction::keys(&arguments[0], paren),
+            NativeFunction::Substring => self.substring(&arguments, paren),
+            // case mapping follows Unicode, so a character can become several, e.g. "ß" to "SS"
+            NativeFunction::Upper => Ok(Literal::String(string(0)?.to_uppercase())),
+            NativeFunction::Lower => Ok(Literal::String(string(0)?.to_lowercase())),
+            NativeFunction::Trim => Ok(Literal::String(string(0)?.trim().to_string())),
+            NativeFunction::Split => Ok(NativeFunction::split(string(0)?, string(1)?)),
+            NativeFunction::Replace => {
+                Ok(Literal::String(string(0)?.replace(string(1)?, string(2)?)))
+            }
+            NativeFunction::Type => Ok(Literal::String(arguments[0].type_name().to_string())),
+            NativeFunction::IsInstance => NativeFunction::is_instance(&arguments, paren),
+            NativeFunction::Assert => NativeFunction::assert(
+                arguments[0].is_truthy(),
+                || "Assertion failed.".to_string(),
+                paren,
+            ),
+            // both values are shown quoted where they're strings, so "1" and 1 can be told apart
+            NativeFunction::AssertEq => NativeFunction::assert(
+                Interpreter::is_equal(arguments[0].clone(), arguments[1].clone()),
+                || {
+                    format!(
+                        "Assertion failed: {} != {}.",
+                        arguments[0].debug_string(),
+                        arguments[1].debug_string()
+                    )
+                },
+                paren,
+            ),
+            // the break unwinds the whole script, it's up to whoever is running it to end the process
+            NativeFunction::Exit => NativeFunction::exit(number(0)?, paren),
+            // a new list each call, so a script changing it doesn't change what later calls see
+            NativeFunction::Args => {
+                let args = interpreter.script_args.iter().cloned().map(Literal::String);
+                Ok(Literal::List(Rc::new(RefCell::new(args.collect()))))
+            }
         }
     }
 
     fn arity(&self) -> i32 {
-        0
+        match self {
+            NativeFunction::Clock
+            | NativeFunction::ClockMillis
+            | NativeFunction::ReadLine
+            | NativeFunction::Random
+            | NativeFunction::Args => 0,
+            NativeFunction::Pow
+            | NativeFunction::Min
+            | NativeFunction::Max
+            | NativeFunction::RandomInt
+            | NativeFunction::Split
+            | NativeFunction::IsInstance
+            | NativeFunction::AssertEq => 2,
+            NativeFunction::Substring | NativeFunction::Clamp | NativeFunction::Replace => 3,
+            _ => 1,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn equals(&self, other: &dyn Callable) -> bool {
+        same_callable(self, other)
     }
 }
 
@@ -105,3 +897,75 @@ impl Display for NativeFunction {
         write!(f, "<native fn>")
     }
 }
+
+pub type HostFn = dyn Fn(&mut Interpreter, Vec<Literal>) -> Result<Literal, RuntimeBreak>;
+
+// a function supplied by whoever is embedding the interpreter, see Interpreter::define_native
+#[derive(Clone)]
+pub struct HostFunction {
+    name: String,
+    arity: i32,
+    function: Rc<HostFn>,
+}
+
+impl HostFunction {
+    pub fn new(name: String, arity: i32, function: Rc<HostFn>) -> Self {
+        Self {
+            name,
+            arity,
+            function,
+        }
+    }
+}
+
+impl Debug for HostFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HostFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+// two host functions are only equal if they share the same closure
+impl PartialEq for HostFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+impl Callable for HostFunction {
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Literal>,
+        paren: &Token,
+    ) -> Result<Literal, RuntimeBreak> {
+        // the host can't see the source, so an error it makes without a position is placed at the call
+        (self.function)(interpreter, arguments).map_err(|err| match err {
+            RuntimeBreak::RuntimeErrorBreak(mut re) if re.token.line == 0 => {
+                re.token = paren.clone();
+                RuntimeBreak::RuntimeErrorBreak(re)
+            }
+            other => other,
+        })
+    }
+
+    fn arity(&self) -> i32 {
+        self.arity
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn equals(&self, other: &dyn Callable) -> bool {
+        same_callable(self, other)
+    }
+}
+
+impl Display for HostFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
@@ -1,66 +1,217 @@
-use crate::RuntimeBreak;
+use crate::error::RuntimeBreak;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 
 use crate::{
+    error::RuntimeError,
     token::{Literal, Token},
-    RuntimeError,
 };
 
 #[derive(Debug)]
 pub struct Environment {
     enclosing: Option<Rc<RefCell<Environment>>>,
-    values: HashMap<String, Literal>,
+    // only the global environment is looked up by name, as the resolver can't know every global
+    values: HashMap<Rc<str>, Literal>,
+    // locals are stored in the order they're defined, which is the slot the resolver gave them
+    slots: Vec<Literal>,
+    // the name of each slot, for error messages
+    names: Vec<Rc<str>>,
 }
 
 impl Environment {
     // global environment will pass in None for enclosing
     pub fn new(enclosing: Option<Rc<RefCell<Environment>>>) -> Self {
-        if let Some(enc) = enclosing {
-            Self {
-                enclosing: Some(enc),
-                values: HashMap::new(),
-            }
+        Self {
+            enclosing,
+            values: HashMap::new(),
+            slots: vec![],
+            names: vec![],
+        }
+    }
+
+    fn is_global(&self) -> bool {
+        self.enclosing.is_none()
+    }
+
+    pub fn define(&mut self, name: Rc<str>, value: Literal) {
+        if self.is_global() {
+            self.values.insert(name, value);
         } else {
-            Self {
-                enclosing: None,
-                values: HashMap::new(),
-            }
+            self.slots.push(value);
+            self.names.push(name);
         }
     }
 
-    pub fn define(&mut self, name: String, value: Literal) {
-        self.values.insert(name, value);
+    // the slot of a local found by its name, for the few places that don't have a resolved slot
+    fn slot_of(&self, name: &Token) -> Option<usize> {
+        self.names.iter().position(|n| *n == name.lexeme)
     }
 
     // can't create a new variable
-    pub fn assign(&mut self, name: Token, value: Literal) -> Result<(), RuntimeBreak> {
-        if self.values.contains_key(&name.lexeme) {
-            self.values.insert(name.lexeme, value);
+    pub fn assign(&mut self, name: &Token, value: Literal) -> Result<(), RuntimeBreak> {
+        if let Some(slot) = self.values.get_mut(&name.lexeme) {
+            *slot = value;
+            Ok(())
+        } else if let Some(slot) = self.slot_of(name) {
+            self.slots[slot] = value;
             Ok(())
         } else if let Some(ref mut enc) = self.enclosing {
             enc.borrow_mut().assign(name, value)
         } else {
-            Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError {
-                token: name.clone(),
-                message: format!("Undefined variable '{}'.", &name.lexeme),
-            }))
+            Err(RuntimeBreak::RuntimeErrorBreak(self.undefined(name)))
         }
     }
 
-    pub fn get(&self, name: Token) -> Result<Literal, RuntimeError> {
-        if self.values.contains_key(&name.lexeme) {
-            Ok(self.values.get(&name.lexeme).unwrap().clone())
+    pub fn get(&self, name: &Token) -> Result<Literal, RuntimeError> {
+        if let Some(value) = self.values.get(&name.lexeme) {
+            Ok(value.clone())
+        } else if let Some(slot) = self.slot_of(name) {
+            Ok(self.slots[slot].clone())
         }
         // recursively search for the variable in enclosing environment
         else if let Some(ref enc) = self.enclosing {
             enc.borrow().get(name)
         } else {
-            Err(RuntimeError {
-                token: name.clone(),
-                message: format!("Undefined variable '{}'.", &name.lexeme),
-            })
+            Err(self.undefined(name))
+        }
+    }
+
+    // reads slot `slot` of the environment exactly `distance` scopes up the chain, as worked out by the resolver
+    pub fn get_at(
+        &self,
+        distance: usize,
+        slot: usize,
+        name: &Token,
+    ) -> Result<Literal, RuntimeError> {
+        if distance == 0 {
+            self.slots
+                .get(slot)
+                .cloned()
+                .ok_or_else(|| self.undefined(name))
+        } else if let Some(ref enc) = self.enclosing {
+            enc.borrow().get_at(distance - 1, slot, name)
+        } else {
+            Err(self.undefined(name))
+        }
+    }
+
+    pub fn assign_at(
+        &mut self,
+        distance: usize,
+        slot: usize,
+        name: &Token,
+        value: Literal,
+    ) -> Result<(), RuntimeBreak> {
+        if distance == 0 {
+            match self.slots.get_mut(slot) {
+                Some(current) => {
+                    *current = value;
+                    Ok(())
+                }
+                None => Err(RuntimeBreak::RuntimeErrorBreak(self.undefined(name))),
+            }
+        } else if let Some(ref mut enc) = self.enclosing {
+            enc.borrow_mut().assign_at(distance - 1, slot, name, value)
+        } else {
+            Err(RuntimeBreak::RuntimeErrorBreak(self.undefined(name)))
+        }
+    }
+
+    // the variables defined in this environment alone, sorted by name
+    pub fn snapshot(&self) -> Vec<(Rc<str>, Literal)> {
+        let mut variables: Vec<(Rc<str>, Literal)> = self
+            .values
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .chain(self.names.iter().cloned().zip(self.slots.iter().cloned()))
+            .collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
+    }
+
+    // every name visible from this environment, including those of enclosing environments
+    pub fn names(&self) -> Vec<Rc<str>> {
+        let mut names: Vec<Rc<str>> = self.values.keys().cloned().collect();
+        names.extend(self.names.iter().cloned());
+        if let Some(ref enc) = self.enclosing {
+            names.extend(enc.borrow().names());
+        }
+        names
+    }
+
+    // suggests a visible name when the undefined one looks like a typo of it
+    pub fn undefined(&self, name: &Token) -> RuntimeError {
+        let mut message = format!("Undefined variable '{}'.", name.lexeme);
+        if let Some(suggestion) = closest_name(&name.lexeme, &self.names()) {
+            message.push_str(&format!(" Did you mean '{suggestion}'?"));
         }
+        RuntimeError::new(name.clone(), message)
+    }
+}
+
+// the candidate within an edit distance of 2, preferring the closest and then the alphabetically first
+fn closest_name<'a>(name: &str, candidates: &'a [Rc<str>]) -> Option<&'a Rc<str>> {
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| (1..=2).contains(distance))
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+// the Levenshtein distance, counting single character insertions, deletions and substitutions
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::TokenType;
+
+    fn token(name: &str) -> Token {
+        Token::new(TokenType::Identifier, name.to_string(), Literal::Null, 1, 1)
+    }
+
+    #[test]
+    fn edit_distance_counts_single_character_changes() {
+        assert_eq!(edit_distance("length", "length"), 0);
+        assert_eq!(edit_distance("lenght", "length"), 2);
+        assert_eq!(edit_distance("count", "cont"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn undefined_variable_suggests_a_close_name() {
+        let globals = Rc::new(RefCell::new(Environment::new(None)));
+        globals.borrow_mut().define("length".into(), Literal::Null);
+        let mut local = Environment::new(Some(globals));
+        local.define("counter".into(), Literal::Null);
+
+        assert_eq!(
+            local.get(&token("lenght")).unwrap_err().message,
+            "Undefined variable 'lenght'. Did you mean 'length'?"
+        );
+        assert_eq!(
+            local.undefined(&token("countr")).message,
+            "Undefined variable 'countr'. Did you mean 'counter'?"
+        );
+        assert_eq!(
+            local.undefined(&token("total")).message,
+            "Undefined variable 'total'."
+        );
     }
 }
@@ -1,11 +1,15 @@
 use crate::token::Literal;
 use std::fmt::Display;
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::rc::Rc;
 
 use crate::token::{Token, TokenType};
 
 #[derive(Debug)]
 pub struct LoxError {
     pub line: u32,
+    pub column: u32,
     pub message: String,
 }
 
@@ -13,14 +17,20 @@ impl Display for LoxError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "[line {}] Error while scanning: {}",
-            self.line, self.message
+            "[line {}, col {}] Error while scanning: {}",
+            self.line, self.column, self.message
         )
     }
 }
 
 impl std::error::Error for LoxError {}
 
+impl SourceError for LoxError {
+    fn span(&self) -> Option<(u32, u32, usize)> {
+        Some((self.line, self.column, 1))
+    }
+}
+
 #[derive(Debug)]
 pub struct ParseError {
     pub token: Token,
@@ -32,14 +42,14 @@ impl Display for ParseError {
         if self.token.ttype == TokenType::Eof {
             write!(
                 f,
-                "Syntax error: Line {} at end: {}",
-                self.token.line, self.message
+                "[line {}, col {}] Syntax error at end: {}",
+                self.token.line, self.token.column, self.message
             )
         } else {
             write!(
                 f,
-                "Syntax error: Line {} at '{}': {}",
-                self.token.line, self.token.lexeme, self.message
+                "[line {}, col {}] Syntax error at '{}': {}",
+                self.token.line, self.token.column, self.token.lexeme, self.message
             )
         }
     }
@@ -47,10 +57,20 @@ impl Display for ParseError {
 
 impl std::error::Error for ParseError {}
 
+impl SourceError for ParseError {
+    fn span(&self) -> Option<(u32, u32, usize)> {
+        Some(token_span(&self.token))
+    }
+}
+
 #[derive(Debug)]
 pub enum RuntimeBreak {
     RuntimeErrorBreak(RuntimeError),
     ReturnBreak(ReturnError),
+    LoopBreak,
+    LoopContinue,
+    // the script called exit, which ends it with the given code whatever it was doing
+    ExitBreak(i32),
 }
 
 impl Display for RuntimeBreak {
@@ -59,26 +79,235 @@ impl Display for RuntimeBreak {
             RuntimeBreak::RuntimeErrorBreak(re) => {
                 write!(
                     f,
-                    "Runtime error at {:?}: {} [line {}]",
-                    re.token.ttype, re.message, re.token.line
-                )
-            }
-            RuntimeBreak::ReturnBreak(re) => {
-                write!(f, "Value returned: {:#?}", re.value)
+                    "Runtime error at {:?}: {} [line {}, col {}",
+                    re.token.ttype, re.message, re.token.line, re.token.column
+                )?;
+                match re.file {
+                    Some(ref file) => write!(f, " in '{}']", file.name)?,
+                    None => write!(f, "]")?,
+                }
+                for frame in re.trace.iter().take(MAX_TRACE_FRAMES) {
+                    write!(f, "\n  {frame}")?;
+                }
+                if re.trace.len() > MAX_TRACE_FRAMES {
+                    write!(f, "\n  ... {} more", re.trace.len() - MAX_TRACE_FRAMES)?;
+                }
+                Ok(())
             }
+            // the parser rejects these outside of functions and loops, and interpret turns an escaped
+            // return into a runtime error, so these are never shown for a script
+            RuntimeBreak::ReturnBreak(_) => write!(f, "'return' used outside of a function"),
+            RuntimeBreak::LoopBreak => write!(f, "'break' used outside of a loop"),
+            RuntimeBreak::LoopContinue => write!(f, "'continue' used outside of a loop"),
+            RuntimeBreak::ExitBreak(code) => write!(f, "exited with code {code}"),
         }
     }
 }
 
 impl std::error::Error for RuntimeBreak {}
 
+impl SourceError for RuntimeBreak {
+    fn span(&self) -> Option<(u32, u32, usize)> {
+        match self {
+            RuntimeBreak::RuntimeErrorBreak(re) => Some(token_span(&re.token)),
+            _ => None,
+        }
+    }
+
+    fn file_source(&self) -> Option<&str> {
+        match self {
+            RuntimeBreak::RuntimeErrorBreak(re) => re.file.as_ref().map(|file| &*file.source),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct RuntimeError {
     pub token: Token,
     pub message: String,
+    // the calls that were in progress when the error happened, innermost first
+    pub trace: Vec<CallFrame>,
+    // the imported file the token is from, None when it's from the program being run
+    pub file: Option<Rc<SourceFile>>,
+}
+
+impl RuntimeError {
+    pub fn new(token: Token, message: String) -> Self {
+        Self {
+            token,
+            message,
+            trace: vec![],
+            file: None,
+        }
+    }
+}
+
+// an imported file, kept so errors in its code can show the line they happened on
+#[derive(Debug, PartialEq)]
+pub struct SourceFile {
+    // as written in the import statement
+    pub name: String,
+    pub path: PathBuf,
+    pub source: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallFrame {
+    pub function: String,
+    // the line the function was called from
+    pub line: u32,
+}
+
+impl Display for CallFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "in {}() called from line {}", self.function, self.line)
+    }
 }
 
+// deep recursion would otherwise bury the error under thousands of identical lines
+const MAX_TRACE_FRAMES: usize = 10;
+
 #[derive(Debug)]
 pub struct ReturnError {
+    pub keyword: Token,
     pub value: Literal,
 }
+
+// any error from running a source string, tagged with the stage that produced it
+#[derive(Debug)]
+pub enum Error {
+    Scan(LoxError),
+    Parse(ParseError),
+    Resolve(ParseError),
+    Runtime(RuntimeBreak),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Scan(err) => err.fmt(f),
+            Error::Parse(err) | Error::Resolve(err) => err.fmt(f),
+            Error::Runtime(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl SourceError for Error {
+    fn span(&self) -> Option<(u32, u32, usize)> {
+        match self {
+            Error::Scan(err) => err.span(),
+            Error::Parse(err) | Error::Resolve(err) => err.span(),
+            Error::Runtime(err) => err.span(),
+        }
+    }
+
+    fn file_source(&self) -> Option<&str> {
+        match self {
+            Error::Runtime(err) => err.file_source(),
+            _ => None,
+        }
+    }
+}
+
+// errors which can point at the offending part of the source
+pub trait SourceError: std::error::Error {
+    // line, column and length in characters of the offending text
+    fn span(&self) -> Option<(u32, u32, usize)>;
+
+    // the source the span is in when it isn't the program being run, e.g. an imported file
+    fn file_source(&self) -> Option<&str> {
+        None
+    }
+}
+
+fn token_span(token: &Token) -> (u32, u32, usize) {
+    (
+        token.line,
+        token.column,
+        token.lexeme.chars().count().max(1),
+    )
+}
+
+// lines longer than this are cut down to a window around the error
+const MAX_SNIPPET_WIDTH: usize = 80;
+
+// renders the source line containing the error with carets underneath the offending text, e.g.
+//   2 | print -"x";
+//     |       ^
+pub fn render_snippet(source: &str, line: u32, column: u32, width: usize) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let Some(last_line) = lines.len().checked_sub(1) else {
+        return String::new();
+    };
+
+    // the end of file can sit on an empty line past the last one, so point just after the last line instead
+    let (index, column) = if (line as usize) > lines.len() {
+        (last_line, lines[last_line].chars().count() + 1)
+    } else {
+        (line.max(1) as usize - 1, column.max(1) as usize)
+    };
+    let text: Vec<char> = lines[index].chars().collect();
+
+    // keep the caret in view on long lines
+    let mut start = 0;
+    let mut end = text.len();
+    if text.len() > MAX_SNIPPET_WIDTH {
+        start = (column - 1).saturating_sub(MAX_SNIPPET_WIDTH / 2);
+        end = (start + MAX_SNIPPET_WIDTH).min(text.len());
+        start = end.saturating_sub(MAX_SNIPPET_WIDTH);
+    }
+    let prefix = if start > 0 { "..." } else { "" };
+    let suffix = if end < text.len() { "..." } else { "" };
+    let shown: String = text[start..end].iter().collect();
+
+    let caret_offset = prefix.len() + (column - 1).saturating_sub(start);
+    let caret_width = width.min((end + 1).saturating_sub(column).max(1));
+
+    let line_number = (index + 1).to_string();
+    let gutter = " ".repeat(line_number.len());
+
+    let mut snippet = String::new();
+    let _ = writeln!(snippet, "{line_number} | {prefix}{shown}{suffix}");
+    let _ = writeln!(
+        snippet,
+        "{gutter} | {}{}",
+        " ".repeat(caret_offset),
+        "^".repeat(caret_width)
+    );
+    snippet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snippet_underlines_the_token() {
+        let snippet = render_snippet("var a = 1;\nprint -\"x\";", 2, 7, 1);
+
+        assert_eq!(snippet, "2 | print -\"x\";\n  |       ^\n");
+    }
+
+    #[test]
+    fn snippet_at_end_of_file_points_after_the_last_line() {
+        let snippet = render_snippet("print 1\n", 2, 1, 1);
+
+        assert_eq!(snippet, "1 | print 1\n  |        ^\n");
+    }
+
+    #[test]
+    fn long_lines_are_cut_down_around_the_error() {
+        let source = format!("{}bad{}", "a".repeat(100), "b".repeat(100));
+        let snippet = render_snippet(&source, 1, 101, 3);
+        let lines: Vec<&str> = snippet.lines().collect();
+
+        assert!(lines[0].starts_with("1 | ..."));
+        assert!(lines[0].ends_with("..."));
+        let caret = lines[1].find('^').unwrap();
+        assert_eq!(&lines[0][caret..caret + 3], "bad");
+        assert!(lines[1].ends_with("^^^"));
+    }
+}
@@ -2,54 +2,302 @@ use crate::callable::*;
 use crate::environment::*;
 use crate::error::*;
 use crate::parser::*;
-use crate::token::Literal;
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
 use crate::token::TokenType;
+use crate::token::{format_number, Literal, Token};
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, stdin, stdout, BufRead, Write};
+use std::path::PathBuf;
 use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1000;
+
+// each nested call takes a good few kilobytes of Rust stack, more so in debug builds
+// so the interpreter needs a thread with this much stack to reach DEFAULT_MAX_CALL_DEPTH
+pub const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+// the longest string, in bytes, that repeating one with * can make
+// well short of what would exhaust memory, which aborts the process rather than being a runtime error
+pub const MAX_REPEAT_LENGTH: usize = 64 * 1024 * 1024;
+
+// where the resolver found a local variable: how many environments out, and its slot in that one
+#[derive(Debug, Clone, Copy)]
+struct Resolved {
+    depth: usize,
+    slot: usize,
+}
 
 pub struct Interpreter {
     pub globals: Rc<RefCell<Environment>>,
+    // the natives the globals started out with, to tell them apart from what the program defined
+    builtins: HashMap<Rc<str>, Literal>,
     pub environment: Rc<RefCell<Environment>>,
+    // where each resolved local variable lives, keyed by expression id
+    locals: HashMap<usize, Resolved>,
+    // where print statements write to
+    out: Box<dyn Write>,
+    // the user functions currently being run, outermost first
+    pub call_stack: Vec<CallFrame>,
+    // calls nested deeper than this are a runtime error rather than overflowing the Rust stack
+    pub max_call_depth: usize,
+    // where read_line reads from, None reads stdin without holding on to its lock
+    input: Option<Box<dyn BufRead>>,
+    // when the interpreter was created, clock and clock_millis count from here
+    start: Instant,
+    // statements and expressions run so far by the current call to interpret
+    steps: usize,
+    // stops scripts that run on for too long, None means no limit
+    pub step_limit: Option<usize>,
+    // the file being run, imports in it are relative to its directory
+    // None for source that didn't come from a file, whose imports are relative to the working directory
+    pub script_path: Option<PathBuf>,
+    // reading files is off limits, so import is a runtime error
+    sandboxed: bool,
+    // the files whose imports are currently being run, innermost last, to catch import cycles
+    importing: Vec<Rc<SourceFile>>,
+    // the imported file whose code is running, None for the program itself
+    // functions remember theirs, so errors in them show the right file however they were called
+    pub file: Option<Rc<SourceFile>>,
+    // what the args native returns, the command line arguments after the script path
+    pub script_args: Vec<String>,
+    // xorshift64* state behind random and random_int, never zero
+    random_state: u64,
+    // logs each statement to stderr before it runs, see --trace
+    pub trace: bool,
+    // how many statements the one being traced is nested in
+    trace_depth: usize,
 }
 
 impl Interpreter {
     pub fn new() -> Self {
-        let globals = Interpreter::insert_native_functions();
+        Interpreter::with_output(Box::new(stdout()))
+    }
+
+    pub fn with_output(out: Box<dyn Write>) -> Self {
+        let (globals, builtins) = Interpreter::insert_native_functions(false);
+
+        let mut interpreter = Self {
+            environment: globals.clone(),
+            globals,
+            builtins,
+            locals: HashMap::new(),
+            out,
+            call_stack: vec![],
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            input: None,
+            start: Instant::now(),
+            steps: 0,
+            step_limit: None,
+            script_path: None,
+            sandboxed: false,
+            importing: vec![],
+            file: None,
+            script_args: vec![],
+            random_state: 1,
+            trace: false,
+            trace_depth: 0,
+        };
+        // a different sequence every run unless the script or embedder picks a seed
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        interpreter.seed(now.as_nanos() as u64);
+        interpreter
+    }
+
+    pub fn with_io(input: Box<dyn BufRead>, out: Box<dyn Write>) -> Self {
+        Self {
+            input: Some(input),
+            ..Interpreter::with_output(out)
+        }
+    }
 
+    // for running untrusted scripts, natives like clock and read_line aren't defined at all
+    pub fn new_sandboxed() -> Self {
+        let (globals, builtins) = Interpreter::insert_native_functions(true);
         Self {
             environment: globals.clone(),
             globals,
+            builtins,
+            sandboxed: true,
+            ..Interpreter::new()
         }
     }
 
-    fn insert_native_functions() -> Rc<RefCell<Environment>> {
+    pub fn with_step_limit(limit: usize) -> Self {
+        Self {
+            step_limit: Some(limit),
+            ..Interpreter::new()
+        }
+    }
+
+    // forgets every variable, function and class the program has defined, as if the interpreter was new
+    // where input and output go, the limits and the script's arguments are kept
+    pub fn reset(&mut self) {
+        let (globals, builtins) = Interpreter::insert_native_functions(self.sandboxed);
+        self.environment = globals.clone();
+        self.globals = globals;
+        self.builtins = builtins;
+        self.locals.clear();
+        self.call_stack.clear();
+        self.importing.clear();
+        self.file = None;
+        self.steps = 0;
+    }
+
+    // makes a Rust function callable from scripts as a global
+    pub fn define_native<F>(&mut self, name: &str, arity: i32, function: F)
+    where
+        F: Fn(&mut Interpreter, Vec<Literal>) -> Result<Literal, RuntimeBreak> + 'static,
+    {
+        let host = HostFunction::new(name.to_string(), arity, Rc::new(function));
+        self.globals
+            .borrow_mut()
+            .define(name.into(), Literal::Callable(Rc::new(host)));
+    }
+
+    // reads a line, newline included, returning 0 at the end of input
+    pub fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        match self.input {
+            Some(ref mut input) => input.read_line(buf),
+            None => stdin().read_line(buf),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    // the same seed always gives the same sequence from random and random_int
+    pub fn seed(&mut self, seed: u64) {
+        // splitmix64, so nearby seeds start far apart
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        self.random_state = (z ^ (z >> 31)).max(1);
+    }
+
+    pub fn next_random(&mut self) -> u64 {
+        let mut x = self.random_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.random_state = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn insert_native_functions(
+        sandboxed: bool,
+    ) -> (Rc<RefCell<Environment>>, HashMap<Rc<str>, Literal>) {
         let globals = Rc::new(RefCell::new(Environment::new(None)));
-        globals.borrow_mut().define(
-            "clock".to_string(),
-            Literal::NativeFunc(NativeFunction::Clock),
-        );
-        globals
+        let mut builtins = HashMap::new();
+        for native in NativeFunction::ALL {
+            if sandboxed && !native.is_safe() {
+                continue;
+            }
+            let name: Rc<str> = native.name().into();
+            let value = Literal::Callable(Rc::new(native));
+            globals.borrow_mut().define(name.clone(), value.clone());
+            builtins.insert(name, value);
+        }
+        (globals, builtins)
+    }
+
+    // whether the global is still the native it started out as, rather than something the program defined
+    // an alias like `var f = clock;` or a redefined `clock` is the program's own
+    pub fn is_builtin(&self, name: &str, value: &Literal) -> bool {
+        match (self.builtins.get(name), value) {
+            (Some(Literal::Callable(builtin)), Literal::Callable(value)) => {
+                Rc::ptr_eq(builtin, value)
+            }
+            _ => false,
+        }
     }
 
     pub fn interpret(&mut self, stmts: Vec<Stmt>) -> Result<(), RuntimeBreak> {
-        for stmt in stmts {
-            self.execute(stmt)?;
+        self.steps = 0;
+        for stmt in &stmts {
+            match self.execute(stmt) {
+                // only possible for statements that didn't come from the parser, which rejects them
+                Err(RuntimeBreak::ReturnBreak(re)) => {
+                    return Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                        re.keyword,
+                        "Can't return from top-level code.".to_string(),
+                    )))
+                }
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+
+    // like interpret, but echoes the value of a trailing expression statement as a REPL would
+    pub fn interpret_repl(&mut self, mut stmts: Vec<Stmt>) -> Result<(), RuntimeBreak> {
+        let last = match stmts.last() {
+            Some(Stmt::ExprStmt(_)) => stmts.pop(),
+            _ => None,
+        };
+
+        self.interpret(stmts)?;
+
+        if let Some(Stmt::ExprStmt(expr)) = last {
+            let value = self.evaluate(&expr)?;
+            let shown = self.stringify(&value)?;
+            writeln!(self.out, "{shown}").expect("Failed to write output");
         }
         Ok(())
     }
 
-    fn execute(&mut self, stmt: Stmt) -> Result<(), RuntimeBreak> {
+    // statements and expressions are borrowed from the tree, so loops and calls run them without copying
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), RuntimeBreak> {
+        self.steps += 1;
+        if !self.trace {
+            return self.execute_stmt(stmt);
+        }
+        // stderr, so the trace doesn't end up mixed into the program's own output
+        eprintln!("{}", self.trace_line(stmt));
+        self.trace_depth += 1;
+        let result = self.execute_stmt(stmt);
+        self.trace_depth -= 1;
+        result
+    }
+
+    // statements are indented by how deeply they're nested, e.g. "  [line 3] print"
+    fn trace_line(&self, stmt: &Stmt) -> String {
+        let line = stmt.line().map_or("?".to_string(), |line| line.to_string());
+        format!(
+            "{}[line {line}] {}",
+            "  ".repeat(self.trace_depth),
+            stmt.kind()
+        )
+    }
+
+    fn execute_stmt(&mut self, stmt: &Stmt) -> Result<(), RuntimeBreak> {
         match stmt {
             Stmt::ExprStmt(expr) => match self.evaluate(expr) {
                 Ok(_l) => Ok(()),
                 Err(err) => Err(err),
             },
             Stmt::PrintStmt(expr) => self.eval_print_stmt(expr),
-            Stmt::IfStmt(ifstmt) => self.eval_if_stmt(*ifstmt),
-            Stmt::WhileStmt(whilestmt) => self.eval_while_stmt(*whilestmt),
+            Stmt::IfStmt(ifstmt) => self.eval_if_stmt(ifstmt),
+            Stmt::WhileStmt(whilestmt) => self.eval_while_stmt(whilestmt),
+            Stmt::DoWhileStmt(dowhile) => self.eval_do_while_stmt(dowhile),
+            Stmt::SwitchStmt(switch) => self.eval_switch_stmt(switch),
+            Stmt::ForEachStmt(foreach) => self.eval_for_each_stmt(foreach),
             Stmt::VarDeclStmt(var) => self.eval_var_decl_stmt(var),
+            Stmt::MultiVarDeclStmt(vars) => {
+                vars.iter().try_for_each(|var| self.eval_var_decl_stmt(var))
+            }
+            Stmt::ClassDeclStmt(class) => self.eval_class_decl_stmt(class),
             Stmt::FuncDeclStmt(func) => self.eval_func_decl_stmt(func),
             Stmt::ReturnStmt(ret) => self.eval_return_stmt(ret),
+            Stmt::ImportStmt(import) => self.eval_import_stmt(import),
+            Stmt::BreakStmt(_) => Err(RuntimeBreak::LoopBreak),
+            Stmt::ContinueStmt(_) => Err(RuntimeBreak::LoopContinue),
             Stmt::BlockStmt(block) => self.eval_block(block),
             _ => Ok(()),
         }
@@ -57,7 +305,7 @@ impl Interpreter {
 
     pub fn execute_block(
         &mut self,
-        statements: Vec<Stmt>,
+        statements: &[Stmt],
         env: Rc<RefCell<Environment>>,
     ) -> Result<(), RuntimeBreak> {
         let previous = Rc::clone(&self.environment);
@@ -74,273 +322,891 @@ impl Interpreter {
         Ok(())
     }
 
-    fn evaluate(&mut self, expression: Expr) -> Result<Literal, RuntimeBreak> {
+    // like execute_block, but for a single expression
+    pub fn evaluate_in(
+        &mut self,
+        expr: &Expr,
+        env: Rc<RefCell<Environment>>,
+    ) -> Result<Literal, RuntimeBreak> {
+        let previous = std::mem::replace(&mut self.environment, env);
+        let result = self.evaluate(expr);
+        self.environment = previous;
+        result
+    }
+
+    // every step is counted, but the limit is only checked on each loop iteration and function call
+    // as those are the only way a script can keep running, and they have a token to report the error at
+    pub fn check_step_limit(&self, token: &Token) -> Result<(), RuntimeBreak> {
+        match self.step_limit {
+            Some(limit) if self.steps > limit => Err(RuntimeBreak::RuntimeErrorBreak(
+                RuntimeError::new(token.clone(), "Execution step limit exceeded.".to_string()),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // called by the resolver for every local variable reference
+    pub fn resolve(&mut self, id: usize, depth: usize, slot: usize) {
+        self.locals.insert(id, Resolved { depth, slot });
+    }
+
+    // unresolved variables are assumed to be global
+    fn look_up_variable(&self, id: usize, name: &Token) -> Result<Literal, RuntimeBreak> {
+        let value = if let Some(local) = self.locals.get(&id) {
+            self.environment
+                .borrow()
+                .get_at(local.depth, local.slot, name)
+        } else {
+            // the resolver leaves globals unresolved, so they are read straight from the global scope
+            // rather than by walking out to it, which keeps natives cheap to call in tight loops
+            // suggestions come from every scope in reach, not just the globals
+            self.globals
+                .borrow()
+                .get(name)
+                .map_err(|_| self.environment.borrow().undefined(name))
+        };
+        value.map_err(RuntimeBreak::RuntimeErrorBreak)
+    }
+
+    fn evaluate(&mut self, expression: &Expr) -> Result<Literal, RuntimeBreak> {
+        self.steps += 1;
         match expression {
-            Expr::GroupingExpr(g) => self.evaluate(g.expression),
-            Expr::BinaryExpr(b) => self.eval_binary(*b),
-            Expr::UnaryExpr(u) => self.eval_unary(*u),
-            Expr::VarExpr(v) => self.eval_var(*v),
-            Expr::AssignExpr(a) => self.eval_assign(*a),
-            Expr::LogicExpr(l) => self.eval_logic(*l),
-            Expr::CallExpr(c) => self.eval_call(*c),
-            Expr::LitExpr(l) => Ok(l),
+            Expr::GroupingExpr(g) => self.evaluate(&g.expression),
+            // every operand runs in order, but only the last one's value is kept
+            Expr::CommaExpr(c) => {
+                let (last, rest) = c
+                    .expressions
+                    .split_last()
+                    .expect("comma expression is empty");
+                for expr in rest {
+                    self.evaluate(expr)?;
+                }
+                self.evaluate(last)
+            }
+            Expr::BinaryExpr(b) => self.eval_binary(b),
+            Expr::UnaryExpr(u) => self.eval_unary(u),
+            Expr::VarExpr(v) => self.eval_var(v),
+            Expr::AssignExpr(a) => self.eval_assign(a),
+            Expr::LogicExpr(l) => self.eval_logic(l),
+            Expr::TernaryExpr(t) => self.eval_ternary(t),
+            Expr::CallExpr(c) => self.eval_call(c),
+            Expr::LambdaExpr(func) => Ok(Literal::Callable(Rc::new(Function::new(
+                func.as_ref().clone(),
+                self.environment.clone(),
+                false,
+                self.file.clone(),
+            )))),
+            Expr::GetExpr(g) => self.eval_get(g, false),
+            Expr::SetExpr(s) => self.eval_set(s),
+            Expr::SuperExpr(s) => self.eval_super(s, false),
+            Expr::ThisExpr(t) => self.eval_this(t),
+            Expr::ListExpr(list) => {
+                let elements: Result<Vec<Literal>, RuntimeBreak> = list
+                    .elements
+                    .iter()
+                    .map(|element| self.evaluate(element))
+                    .collect();
+                Ok(Literal::List(Rc::new(RefCell::new(elements?))))
+            }
+            Expr::MapExpr(map) => self.eval_map(map),
+            Expr::IndexExpr(i) => self.eval_index(i),
+            Expr::IndexSetExpr(i) => self.eval_index_set(i),
+            Expr::LitExpr(l) => Ok(l.clone()),
         }
     }
 
-    fn eval_block(&mut self, block: Block) -> Result<(), RuntimeBreak> {
+    fn eval_block(&mut self, block: &Block) -> Result<(), RuntimeBreak> {
         self.execute_block(
-            block.statements,
+            &block.statements,
             Rc::new(RefCell::new(Environment::new(Some(
                 self.environment.clone(),
             )))),
         )
     }
 
-    fn eval_assign(&mut self, assignment: Assignment) -> Result<Literal, RuntimeBreak> {
-        let value = self.evaluate(assignment.value)?;
-        self.environment
-            .borrow_mut()
-            .assign(assignment.name, value.clone())?;
+    fn eval_assign(&mut self, assignment: &Assignment) -> Result<Literal, RuntimeBreak> {
+        let value = self.evaluate(&assignment.value)?;
+        if let Some(local) = self.locals.get(&assignment.id) {
+            self.environment.borrow_mut().assign_at(
+                local.depth,
+                local.slot,
+                &assignment.name,
+                value.clone(),
+            )?;
+        } else {
+            let assigned = self
+                .globals
+                .borrow_mut()
+                .assign(&assignment.name, value.clone());
+            if assigned.is_err() {
+                let err = self.environment.borrow().undefined(&assignment.name);
+                return Err(RuntimeBreak::RuntimeErrorBreak(err));
+            }
+        }
         // allows nesting of assign expressions inside other expressions e.g. print a = 2;
         Ok(value)
     }
 
-    fn eval_var(&self, var: Variable) -> Result<Literal, RuntimeBreak> {
-        match self.environment.borrow_mut().get(var.name) {
-            Ok(l) => Ok(l),
-            Err(re) => Err(RuntimeBreak::RuntimeErrorBreak(re)),
+    fn eval_var(&self, var: &Variable) -> Result<Literal, RuntimeBreak> {
+        self.look_up_variable(var.id, &var.name)
+    }
+
+    fn eval_this(&self, this: &This) -> Result<Literal, RuntimeBreak> {
+        self.look_up_variable(this.id, &this.keyword)
+    }
+
+    // called is whether the property is the callee of a call, which a getter can't be
+    fn eval_super(&mut self, sup: &Super, called: bool) -> Result<Literal, RuntimeBreak> {
+        // the resolver rejects "super" outside of a subclass, so this only happens for unresolved statements
+        let Some(distance) = self
+            .locals
+            .get(&sup.id)
+            .map(|local| local.depth)
+            .filter(|&depth| depth > 0)
+        else {
+            return Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                sup.keyword.clone(),
+                "Can't use 'super' outside of a class.".to_string(),
+            )));
+        };
+        // "super" and "this" are each the only variable in their environment
+        let superclass = self
+            .environment
+            .borrow()
+            .get_at(distance, 0, &sup.keyword)
+            .map_err(RuntimeBreak::RuntimeErrorBreak)?;
+        // "this" is always bound in the environment just inside the one defining "super"
+        let this_token = Token::new(
+            TokenType::This,
+            "this".to_string(),
+            Literal::Null,
+            sup.keyword.line,
+            sup.keyword.column,
+        );
+        let object = self
+            .environment
+            .borrow()
+            .get_at(distance - 1, 0, &this_token)
+            .map_err(RuntimeBreak::RuntimeErrorBreak)?;
+
+        let method = if let Literal::Class(superclass) = superclass {
+            superclass.find_method(&sup.method.lexeme).cloned()
+        } else {
+            None
+        };
+
+        match (method, object) {
+            (Some(method), Literal::Instance(_)) if method.is_getter() && called => {
+                Err(Interpreter::getter_called(&sup.method))
+            }
+            (Some(method), Literal::Instance(instance)) if method.is_getter() => {
+                method.bind(instance).call(self, vec![], &sup.method)
+            }
+            (Some(method), Literal::Instance(instance)) => {
+                Ok(Literal::Callable(Rc::new(method.bind(instance))))
+            }
+            _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                sup.method.clone(),
+                format!("Undefined property '{}'.", sup.method.lexeme),
+            ))),
         }
     }
 
-    fn eval_logic(&mut self, logic: Logic) -> Result<Literal, RuntimeBreak> {
-        let left = self.evaluate(logic.left)?;
+    fn eval_logic(&mut self, logic: &Logic) -> Result<Literal, RuntimeBreak> {
+        let left = self.evaluate(&logic.left)?;
+
+        // short circuit when the left operand alone decides the result
+        // a truthy left decides an or, a falsey left decides an and
+        let short_circuits = if logic.operator.ttype == TokenType::Or {
+            left.is_truthy()
+        } else {
+            !left.is_truthy()
+        };
 
-        // check to see if it is possible to short circuit (if left is already true in an or statement)
-        if (logic.operator.ttype == TokenType::Or && left.is_truthy()) || !left.is_truthy() {
+        if short_circuits {
             Ok(left)
         } else {
-            self.evaluate(logic.right)
+            self.evaluate(&logic.right)
         }
     }
 
-    fn eval_call(&mut self, call: Call) -> Result<Literal, RuntimeBreak> {
-        let callee = self.evaluate(call.callee)?;
+    // only the chosen branch is evaluated
+    fn eval_ternary(&mut self, ternary: &Ternary) -> Result<Literal, RuntimeBreak> {
+        if self.evaluate(&ternary.condition)?.is_truthy() {
+            self.evaluate(&ternary.then_branch)
+        } else {
+            self.evaluate(&ternary.else_branch)
+        }
+    }
+
+    fn eval_call(&mut self, call: &Call) -> Result<Literal, RuntimeBreak> {
+        // properties are read knowing they are about to be called, so calling a getter can be refused
+        let callee = match call.callee {
+            Expr::GetExpr(ref get) => self.eval_get(get, true)?,
+            Expr::SuperExpr(ref sup) => self.eval_super(sup, true)?,
+            ref callee => self.evaluate(callee)?,
+        };
 
-        let arguments: Result<Vec<Literal>, RuntimeBreak> = if let Some(args) = call.arguments {
-            args.iter().map(|a| self.evaluate(a.clone())).collect()
+        let arguments: Result<Vec<Literal>, RuntimeBreak> = if let Some(ref args) = call.arguments {
+            args.iter().map(|a| self.evaluate(a)).collect()
         } else {
             Ok(vec![])
         };
 
-        match callee {
-            Literal::Func(f) => match arguments {
-                Ok(args) => {
-                    if f.arity() != args.len() as i32 {
-                        Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError {
-                            token: call.paren,
-                            message: format!(
-                                "Expected {} arguments but got {}",
-                                f.arity(),
-                                args.len()
-                            ),
-                        }))
-                    } else {
-                        f.call(self, args)
-                    }
+        let callable: &dyn Callable = match callee {
+            Literal::Callable(ref c) => c.as_ref(),
+            Literal::Class(ref class) => class,
+            _ => {
+                return Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                    call.paren.clone(),
+                    "Can only call functions and classes".to_string(),
+                )))
+            }
+        };
+
+        let args = arguments?;
+        let (required, total) = (callable.required_arity(), callable.arity());
+        let count = args.len() as i32;
+        if count < required || (count > total && !callable.is_variadic()) {
+            let expected = if callable.is_variadic() {
+                format!("at least {required}")
+            } else if required == total {
+                total.to_string()
+            } else {
+                format!("{required} to {total}")
+            };
+            Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                call.paren.clone(),
+                format!("Expected {expected} arguments but got {count}"),
+            )))
+        } else {
+            callable.call(self, args, &call.paren)
+        }
+    }
+
+    // called is whether the property is the callee of a call, which a getter can't be
+    fn eval_get(&mut self, get: &Get, called: bool) -> Result<Literal, RuntimeBreak> {
+        let object = self.evaluate(&get.object)?;
+
+        if let Literal::Instance(instance) = object {
+            // getters run as soon as they are read, so c.area needs no call parentheses
+            if let Some(getter) = LoxInstance::getter(&instance, &get.name) {
+                if called {
+                    return Err(Interpreter::getter_called(&get.name));
                 }
-                Err(err) => Err(err),
-            },
-            Literal::NativeFunc(nf) => match arguments {
-                Ok(args) => {
-                    if nf.arity() != args.len() as i32 {
-                        Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError {
-                            token: call.paren,
-                            message: format!(
-                                "Expected {} arguments but got {}",
-                                nf.arity(),
-                                args.len()
-                            ),
-                        }))
-                    } else {
-                        nf.call(self, args)
-                    }
+                return getter.call(self, vec![], &get.name);
+            }
+            LoxInstance::get(&instance, &get.name).map_err(RuntimeBreak::RuntimeErrorBreak)
+        } else {
+            Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                get.name.clone(),
+                "Only instances have properties.".to_string(),
+            )))
+        }
+    }
+
+    // c.area() would otherwise quietly call whatever the getter returns
+    fn getter_called(name: &Token) -> RuntimeBreak {
+        RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+            name.clone(),
+            format!("Getter '{}' can't be called.", name.lexeme),
+        ))
+    }
+
+    fn eval_set(&mut self, set: &Set) -> Result<Literal, RuntimeBreak> {
+        let object = self.evaluate(&set.object)?;
+
+        if let Literal::Instance(instance) = object {
+            let value = self.evaluate(&set.value)?;
+            instance.borrow_mut().set(&set.name, value.clone());
+            Ok(value)
+        } else {
+            Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                set.name.clone(),
+                "Only instances have properties.".to_string(),
+            )))
+        }
+    }
+
+    // keys are stringified, so 1 and "1" refer to the same entry
+    fn eval_map(&mut self, map: &Map) -> Result<Literal, RuntimeBreak> {
+        let mut entries = HashMap::new();
+        for (key, value) in &map.entries {
+            let key = self.evaluate(key)?.to_string();
+            let value = self.evaluate(value)?;
+            entries.insert(key, value);
+        }
+        Ok(Literal::Map(Rc::new(RefCell::new(entries))))
+    }
+
+    // reading a missing map key gives nil rather than an error
+    fn eval_index(&mut self, index: &Index) -> Result<Literal, RuntimeBreak> {
+        let object = self.evaluate(&index.object)?;
+        let key = self.evaluate(&index.index)?;
+
+        match object {
+            Literal::List(list) => {
+                let list = list.borrow();
+                let i = Interpreter::checked_index(&key, list.len(), &index.bracket)?;
+                Ok(list[i].clone())
+            }
+            // indexes by character rather than byte so multi-byte characters stay whole
+            Literal::String(s) => {
+                let i = Interpreter::checked_index(&key, s.chars().count(), &index.bracket)?;
+                Ok(Literal::String(s.chars().nth(i).unwrap().to_string()))
+            }
+            Literal::Map(map) => Ok(map
+                .borrow()
+                .get(&key.to_string())
+                .cloned()
+                .unwrap_or(Literal::Null)),
+            _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                index.bracket.clone(),
+                "Only lists, maps and strings can be indexed.".to_string(),
+            ))),
+        }
+    }
+
+    // writing to a missing map key inserts it, lists can only overwrite existing elements
+    fn eval_index_set(&mut self, set: &IndexSet) -> Result<Literal, RuntimeBreak> {
+        let object = self.evaluate(&set.object)?;
+        let key = self.evaluate(&set.index)?;
+        let value = self.evaluate(&set.value)?;
+
+        match object {
+            Literal::List(list) => {
+                let mut list = list.borrow_mut();
+                let i = Interpreter::checked_index(&key, list.len(), &set.bracket)?;
+                list[i] = value.clone();
+            }
+            Literal::String(_) => {
+                return Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                    set.bracket.clone(),
+                    "Strings can't be modified.".to_string(),
+                )))
+            }
+            Literal::Map(map) => {
+                map.borrow_mut().insert(key.to_string(), value.clone());
+            }
+            _ => {
+                return Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                    set.bracket.clone(),
+                    "Only lists, maps and strings can be indexed.".to_string(),
+                )))
+            }
+        }
+        Ok(value)
+    }
+
+    // checks the index is a whole number within the bounds of a list or string
+    // a negative index counts back from the end, so -1 is the last element
+    fn checked_index(key: &Literal, len: usize, bracket: &Token) -> Result<usize, RuntimeBreak> {
+        let error = |message: &str| {
+            Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                bracket.clone(),
+                message.to_string(),
+            )))
+        };
+
+        match *key {
+            Literal::Number(n) if n.fract() != 0.0 => error("Index must be a whole number."),
+            Literal::Number(n) => {
+                let i = if n < 0.0 { n + len as f64 } else { n };
+                if i < 0.0 || i >= len as f64 {
+                    error("Index out of range.")
+                } else {
+                    Ok(i as usize)
                 }
-                Err(err) => Err(err),
-            },
-            _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError {
-                token: call.paren,
-                message: "Can only call functions and classes".to_string(),
-            })),
+            }
+            _ => error("Index must be a number."),
         }
     }
 
-    fn eval_if_stmt(&mut self, ifstmt: If) -> Result<(), RuntimeBreak> {
-        if self.evaluate(ifstmt.condition)?.is_truthy() {
-            self.execute(ifstmt.then_branch)
+    fn eval_if_stmt(&mut self, ifstmt: &If) -> Result<(), RuntimeBreak> {
+        if self.evaluate(&ifstmt.condition)?.is_truthy() {
+            self.execute(&ifstmt.then_branch)
         } else if ifstmt.else_branch != Stmt::ExprStmt(Expr::LitExpr(Literal::Null)) {
-            self.execute(ifstmt.else_branch)
+            self.execute(&ifstmt.else_branch)
         } else {
             Ok(())
         }
     }
 
-    fn eval_while_stmt(&mut self, whilestmt: While) -> Result<(), RuntimeBreak> {
-        let condition = whilestmt.condition;
+    fn eval_while_stmt(&mut self, whilestmt: &While) -> Result<(), RuntimeBreak> {
+        while self.evaluate(&whilestmt.condition)?.is_truthy() {
+            self.check_step_limit(&whilestmt.keyword)?;
+            match self.execute(&whilestmt.body) {
+                Ok(()) | Err(RuntimeBreak::LoopContinue) => (),
+                Err(RuntimeBreak::LoopBreak) => break,
+                Err(err) => return Err(err),
+            }
 
-        while self.evaluate(condition.clone())?.is_truthy() {
-            self.execute(whilestmt.body.clone())?;
+            if let Some(ref increment) = whilestmt.increment {
+                self.evaluate(increment)?;
+            }
         }
         Ok(())
     }
 
-    fn eval_var_decl_stmt(&mut self, var: VarDecl) -> Result<(), RuntimeBreak> {
+    // continue skips to the condition, just as it does in a while loop
+    fn eval_do_while_stmt(&mut self, dowhile: &DoWhile) -> Result<(), RuntimeBreak> {
+        loop {
+            self.check_step_limit(&dowhile.keyword)?;
+            match self.execute(&dowhile.body) {
+                Ok(()) | Err(RuntimeBreak::LoopContinue) => (),
+                Err(RuntimeBreak::LoopBreak) => break,
+                Err(err) => return Err(err),
+            }
+
+            if !self.evaluate(&dowhile.condition)?.is_truthy() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // cases are tried in order and only the first match runs, there's no fallthrough
+    // the discriminant is evaluated once, but case values are only evaluated until one matches
+    fn eval_switch_stmt(&mut self, switch: &Switch) -> Result<(), RuntimeBreak> {
+        let value = self.evaluate(&switch.discriminant)?;
+
+        let mut body = switch.default.as_ref();
+        for (case, statements) in &switch.cases {
+            let case = self.evaluate(case)?;
+            if Interpreter::is_equal(value.clone(), case) {
+                body = Some(statements);
+                break;
+            }
+        }
+
+        match body {
+            Some(statements) => self.execute_block(
+                statements,
+                Rc::new(RefCell::new(Environment::new(Some(
+                    self.environment.clone(),
+                )))),
+            ),
+            None => Ok(()),
+        }
+    }
+
+    // iterates over a copy of the list, so the body changing the list doesn't affect the loop
+    fn eval_for_each_stmt(&mut self, foreach: &ForEach) -> Result<(), RuntimeBreak> {
+        let elements = match self.evaluate(&foreach.iterable)? {
+            Literal::List(list) => list.borrow().clone(),
+            _ => {
+                return Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                    foreach.keyword.clone(),
+                    "Can only iterate over lists.".to_string(),
+                )))
+            }
+        };
+
+        for element in elements {
+            self.check_step_limit(&foreach.keyword)?;
+            let env = Rc::new(RefCell::new(Environment::new(Some(Rc::clone(
+                &self.environment,
+            )))));
+            env.borrow_mut()
+                .define(foreach.variable.lexeme.clone(), element);
+
+            match self.execute_block(std::slice::from_ref(&foreach.body), env) {
+                Ok(()) | Err(RuntimeBreak::LoopContinue) => (),
+                Err(RuntimeBreak::LoopBreak) => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn eval_var_decl_stmt(&mut self, var: &VarDecl) -> Result<(), RuntimeBreak> {
         let value = if var.initialiser != Expr::LitExpr(Literal::Null) {
-            self.evaluate(var.initialiser)?
+            self.evaluate(&var.initialiser)?
         } else {
             Literal::Null
         };
 
-        self.environment.borrow_mut().define(var.name.lexeme, value);
+        self.environment
+            .borrow_mut()
+            .define(var.name.lexeme.clone(), value);
+        Ok(())
+    }
+
+    fn eval_func_decl_stmt(&mut self, func: &FuncDecl) -> Result<(), RuntimeBreak> {
+        self.environment.borrow_mut().define(
+            func.name.lexeme.clone(),
+            Literal::Callable(Rc::new(Function::new(
+                func.clone(),
+                self.environment.clone(),
+                false,
+                self.file.clone(),
+            ))),
+        );
         Ok(())
     }
 
-    fn eval_func_decl_stmt(&mut self, func: FuncDecl) -> Result<(), RuntimeBreak> {
+    fn eval_class_decl_stmt(&mut self, class: &ClassDecl) -> Result<(), RuntimeBreak> {
+        let superclass = if let Some(ref superclass) = class.superclass {
+            let token = superclass.name.clone();
+            match self.eval_var(superclass)? {
+                Literal::Class(superclass) => Some(superclass),
+                _ => {
+                    return Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                        token,
+                        "Superclass must be a class.".to_string(),
+                    )))
+                }
+            }
+        } else {
+            None
+        };
+
+        // define the name first so methods can refer to their own class
         self.environment
             .borrow_mut()
-            .define(func.name.lexeme.clone(), Literal::Func(Function::new(func)));
-        Ok(())
+            .define(class.name.lexeme.clone(), Literal::Null);
+
+        // methods of a subclass close over an extra environment defining "super"
+        let enclosing = self.environment.clone();
+        if let Some(ref superclass) = superclass {
+            self.environment = Rc::new(RefCell::new(Environment::new(Some(enclosing.clone()))));
+            self.environment
+                .borrow_mut()
+                .define("super".into(), Literal::Class(superclass.clone()));
+        }
+
+        let methods: HashMap<Rc<str>, Function> = class
+            .methods
+            .iter()
+            .map(|method| {
+                let is_initializer = &*method.name.lexeme == "init" && !method.getter;
+                (
+                    method.name.lexeme.clone(),
+                    Function::new(
+                        method.clone(),
+                        self.environment.clone(),
+                        is_initializer,
+                        self.file.clone(),
+                    ),
+                )
+            })
+            .collect();
+
+        self.environment = enclosing;
+
+        let class_value = Literal::Class(Rc::new(LoxClass::new(
+            class.name.lexeme.clone(),
+            superclass,
+            methods,
+        )));
+        self.environment
+            .borrow_mut()
+            .assign(&class.name, class_value)
     }
 
-    fn eval_return_stmt(&mut self, ret: Return) -> Result<(), RuntimeBreak> {
+    // runs the whole of another file in the global scope, wherever the import is
+    fn eval_import_stmt(&mut self, import: &Import) -> Result<(), RuntimeBreak> {
+        let error = |message: String| {
+            Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                import.keyword.clone(),
+                message,
+            )))
+        };
+
+        if self.sandboxed {
+            return error("Can't import in a sandboxed interpreter.".to_string());
+        }
+
+        let name = import.path.literal.to_string();
+        let importer = self
+            .importing
+            .last()
+            .map(|file| &file.path)
+            .or(self.script_path.as_ref());
+        let path = match importer.and_then(|file| file.parent()) {
+            Some(dir) => dir.join(&name),
+            None => PathBuf::from(&name),
+        };
+        let (Ok(path), Ok(source)) = (fs::canonicalize(&path), fs::read_to_string(&path)) else {
+            return error(format!("Could not read imported file '{name}'."));
+        };
+
+        let main_script = self
+            .script_path
+            .as_ref()
+            .and_then(|p| fs::canonicalize(p).ok());
+        if self.importing.iter().any(|file| file.path == path)
+            || main_script.as_ref() == Some(&path)
+        {
+            return error(format!("Import cycle through '{name}'."));
+        }
+
+        let mut scanner = Scanner::new(source.clone());
+        let stmts = match scanner.scan_tokens() {
+            Ok(tokens) => Parser::new(tokens.clone())
+                .parse()
+                .map_err(|errors| errors.iter().map(|e| e.to_string()).collect()),
+            Err(errors) => Err(errors.iter().map(|e| e.to_string()).collect::<Vec<_>>()),
+        };
+        let stmts = match stmts {
+            Ok(stmts) => stmts,
+            Err(errors) => return error(format!("Error in '{name}': {}", errors.join("; "))),
+        };
+        if let Err(err) = Resolver::new(self).resolve(&stmts) {
+            return error(format!("Error in '{name}': {err}"));
+        }
+
+        let file = Rc::new(SourceFile { name, path, source });
+        self.importing.push(Rc::clone(&file));
+        let enclosing_file = self.file.replace(Rc::clone(&file));
+        let result = self.execute_block(&stmts, self.globals.clone());
+        self.file = enclosing_file;
+        self.importing.pop();
+
+        // errors from functions called here already know their file, as their trace has been recorded
+        result.map_err(|err| match err {
+            RuntimeBreak::RuntimeErrorBreak(mut re) if re.trace.is_empty() && re.file.is_none() => {
+                re.file = Some(file);
+                RuntimeBreak::RuntimeErrorBreak(re)
+            }
+            err => err,
+        })
+    }
+
+    fn eval_return_stmt(&mut self, ret: &Return) -> Result<(), RuntimeBreak> {
         let mut value = Literal::Null;
         if ret.value != Expr::LitExpr(Literal::Null) {
-            value = self.evaluate(ret.value)?;
+            value = self.evaluate(&ret.value)?;
         }
-        Err(RuntimeBreak::ReturnBreak(ReturnError { value }))
+        Err(RuntimeBreak::ReturnBreak(ReturnError {
+            keyword: ret.keyword.clone(),
+            value,
+        }))
     }
 
-    fn eval_print_stmt(&mut self, expr: Expr) -> Result<(), RuntimeBreak> {
+    fn eval_print_stmt(&mut self, expr: &Expr) -> Result<(), RuntimeBreak> {
         let value = self.evaluate(expr)?;
-        println!("{}", value.as_string());
+        let shown = self.stringify(&value)?;
+        writeln!(self.out, "{shown}").expect("Failed to write output");
         Ok(())
     }
 
-    fn eval_binary(&mut self, b: crate::parser::Binary) -> Result<Literal, RuntimeBreak> {
-        let left = self.evaluate(b.left)?;
-        let right = self.evaluate(b.right)?;
+    // how print and str show a value, like its Display but calling a class's to_string where it has one
+    // which includes instances inside lists and maps
+    pub fn stringify(&mut self, value: &Literal) -> Result<String, RuntimeBreak> {
+        match value {
+            Literal::Instance(instance) => match LoxInstance::to_string_method(instance) {
+                Some(method) => {
+                    // there's no token for the call, so it is placed at the method's name
+                    let token = method.name_token().clone();
+                    Ok(method.call(self, vec![], &token)?.to_string())
+                }
+                None => Ok(value.to_string()),
+            },
+            Literal::List(list) => {
+                // copied out, as a to_string method could change the list while it is shown
+                let elements = list.borrow().clone();
+                let shown = elements
+                    .iter()
+                    .map(|element| self.stringify(element))
+                    .collect::<Result<Vec<String>, RuntimeBreak>>()?;
+                Ok(format!("[{}]", shown.join(", ")))
+            }
+            Literal::Map(map) => {
+                let mut entries: Vec<(String, Literal)> = map
+                    .borrow()
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect();
+                entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+                let shown = entries
+                    .iter()
+                    .map(|(key, value)| Ok(format!("{key}: {}", self.stringify(value)?)))
+                    .collect::<Result<Vec<String>, RuntimeBreak>>()?;
+                Ok(format!("{{{}}}", shown.join(", ")))
+            }
+            _ => Ok(value.to_string()),
+        }
+    }
+
+    fn eval_binary(&mut self, b: &crate::parser::Binary) -> Result<Literal, RuntimeBreak> {
+        let left = self.evaluate(&b.left)?;
+        let right = self.evaluate(&b.right)?;
+        Interpreter::binary_op(&b.operator, left, right)
+    }
 
+    // also used by the optimizer to fold constants, so a folded expression gives exactly what it would at runtime
+    pub fn binary_op(
+        operator: &Token,
+        left: Literal,
+        right: Literal,
+    ) -> Result<Literal, RuntimeBreak> {
         // perform arithmetic, comparison / string concatenation
         match (&left, &right) {
-            (Literal::Number(left_num), Literal::Number(right_num)) => match b.operator.ttype {
+            (Literal::Number(left_num), Literal::Number(right_num)) => match operator.ttype {
                 TokenType::Minus => Ok(Literal::Number(left_num - right_num)),
                 TokenType::Plus => Ok(Literal::Number(left_num + right_num)),
-                TokenType::Slash => {
-                    if right_num != &0.0 {
-                        Ok(Literal::Number(left_num / right_num))
-                    } else {
-                        Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError {
-                            token: b.operator,
-                            message: "Attempted division by zero".to_string(),
-                        }))
-                    }
-                }
+                // dividing by zero follows IEEE 754 like jlox, giving Infinity or NaN rather than an error
+                TokenType::Slash => Ok(Literal::Number(left_num / right_num)),
+                TokenType::Percent => Ok(Literal::Number(left_num % right_num)),
                 TokenType::Star => Ok(Literal::Number(left_num * right_num)),
+                TokenType::StarStar => Ok(Literal::Number(left_num.powf(*right_num))),
                 TokenType::Greater => Ok(Literal::Bool(left_num > right_num)),
                 TokenType::GreaterEqual => Ok(Literal::Bool(left_num >= right_num)),
                 TokenType::Less => Ok(Literal::Bool(left_num < right_num)),
                 TokenType::LessEqual => Ok(Literal::Bool(left_num <= right_num)),
-                TokenType::EqualEqual => Ok(Literal::Bool(self.is_equal(left, right))),
-                TokenType::BangEqual => Ok(Literal::Bool(!self.is_equal(left, right))),
-                _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError {
-                    token: b.operator,
-                    message: "Invalid operator used with two numbers".to_string(),
-                })),
+                TokenType::EqualEqual => Ok(Literal::Bool(Interpreter::is_equal(left, right))),
+                TokenType::BangEqual => Ok(Literal::Bool(!Interpreter::is_equal(left, right))),
+                TokenType::Ampersand
+                | TokenType::Pipe
+                | TokenType::Caret
+                | TokenType::LessLess
+                | TokenType::GreaterGreater => {
+                    Interpreter::eval_bitwise(operator, *left_num, *right_num)
+                }
+                _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                    operator.clone(),
+                    "Invalid operator used with two numbers".to_string(),
+                ))),
             },
             (Literal::String(left_str), Literal::String(right_str)) => {
-                match b.operator.ttype {
+                match operator.ttype {
                     TokenType::Plus => {
                         Ok(Literal::String(left_str.to_owned() + right_str.as_str()))
                     }
-                    TokenType::EqualEqual => Ok(Literal::Bool(self.is_equal(left, right))),
-                    TokenType::BangEqual => Ok(Literal::Bool(!self.is_equal(left, right))),
-                    _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError {
-                        token: b.operator,
-                        message: "Invalid operator used with two strings".to_string(),
-                    })),
+                    // lexicographic by code point, so "Z" < "a" and a prefix sorts first
+                    TokenType::Greater => Ok(Literal::Bool(left_str > right_str)),
+                    TokenType::GreaterEqual => Ok(Literal::Bool(left_str >= right_str)),
+                    TokenType::Less => Ok(Literal::Bool(left_str < right_str)),
+                    TokenType::LessEqual => Ok(Literal::Bool(left_str <= right_str)),
+                    TokenType::EqualEqual => Ok(Literal::Bool(Interpreter::is_equal(left, right))),
+                    TokenType::BangEqual => Ok(Literal::Bool(!Interpreter::is_equal(left, right))),
+                    _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                        operator.clone(),
+                        "Invalid operator used with two strings".to_string(),
+                    ))),
                 }
-                // implicit conversion of Numbers to Strings for concatenation or comparison
             }
-            (Literal::String(left_str), Literal::Number(right_num)) => match b.operator.ttype {
+            // numbers are converted to strings for concatenation, but a string never equals a number
+            (Literal::String(left_str), Literal::Number(right_num)) => match operator.ttype {
                 TokenType::Plus => Ok(Literal::String(
-                    left_str.to_owned() + right_num.to_string().as_str(),
-                )),
-                TokenType::EqualEqual => Ok(Literal::Bool(
-                    self.is_equal(left, Literal::String(right_num.to_string())),
-                )),
-                TokenType::BangEqual => Ok(Literal::Bool(
-                    !self.is_equal(left, Literal::String(right_num.to_string())),
+                    left_str.to_owned() + format_number(*right_num).as_str(),
                 )),
-                _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError {
-                    token: b.operator,
-                    message: "Invalid operator used with a string and a number".to_string(),
-                })),
+                TokenType::Star => Interpreter::repeat(operator, left_str, *right_num),
+                TokenType::EqualEqual => Ok(Literal::Bool(false)),
+                TokenType::BangEqual => Ok(Literal::Bool(true)),
+                _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                    operator.clone(),
+                    "Invalid operator used with a string and a number".to_string(),
+                ))),
             },
-            (Literal::Number(left_num), Literal::String(right_str)) => match b.operator.ttype {
-                TokenType::Plus => Ok(Literal::String(left_num.to_string() + right_str.as_str())),
-                TokenType::EqualEqual => Ok(Literal::Bool(
-                    self.is_equal(Literal::String(left_num.to_string()), right),
-                )),
-                TokenType::BangEqual => Ok(Literal::Bool(
-                    !self.is_equal(Literal::String(left_num.to_string()), right),
+            (Literal::Number(left_num), Literal::String(right_str)) => match operator.ttype {
+                TokenType::Plus => Ok(Literal::String(
+                    format_number(*left_num) + right_str.as_str(),
                 )),
-                _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError {
-                    token: b.operator,
-                    message: "Invalid operator used with a number and a string".to_string(),
-                })),
+                TokenType::Star => Interpreter::repeat(operator, right_str, *left_num),
+                TokenType::EqualEqual => Ok(Literal::Bool(false)),
+                TokenType::BangEqual => Ok(Literal::Bool(true)),
+                _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                    operator.clone(),
+                    "Invalid operator used with a number and a string".to_string(),
+                ))),
             },
-            _ => match b.operator.ttype {
-                TokenType::EqualEqual => Ok(Literal::Bool(self.is_equal(left, right))),
-                TokenType::BangEqual => Ok(Literal::Bool(!self.is_equal(left, right))),
-                _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError {
-                    token: b.operator,
-                    message: "Operands must be two numbers or two strings.".to_string(),
-                })),
+            _ => match operator.ttype {
+                TokenType::EqualEqual => Ok(Literal::Bool(Interpreter::is_equal(left, right))),
+                TokenType::BangEqual => Ok(Literal::Bool(!Interpreter::is_equal(left, right))),
+                _ => Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                    operator.clone(),
+                    "Operands must be two numbers or two strings.".to_string(),
+                ))),
             },
         }
     }
 
-    fn eval_unary(&mut self, u: crate::parser::Unary) -> Result<Literal, RuntimeBreak> {
-        let right = self.evaluate(u.right)?;
+    // "ab" * 3 is "ababab", the count can be on either side
+    // like the bitwise operators, a negative or fractional count is an error rather than being truncated
+    fn repeat(operator: &Token, s: &str, count: f64) -> Result<Literal, RuntimeBreak> {
+        let error = |message: &str| {
+            Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                operator.clone(),
+                message.to_string(),
+            )))
+        };
+
+        if count.fract() != 0.0 || count < 0.0 {
+            return error("A string can only be repeated a whole, non-negative number of times.");
+        }
+        // checked up front, as running out of memory can't be recovered from
+        match s.len().checked_mul(count as usize) {
+            Some(len) if len <= MAX_REPEAT_LENGTH => Ok(Literal::String(s.repeat(count as usize))),
+            _ => error("Repeated string is too long."),
+        }
+    }
+
+    // operands are treated as 64 bit two's complement integers, so negative numbers have their sign bit set
+    // and >> is an arithmetic shift that keeps the sign, e.g. -8 >> 1 == -4
+    // fractional operands are an error rather than being truncated
+    fn eval_bitwise(operator: &Token, left: f64, right: f64) -> Result<Literal, RuntimeBreak> {
+        let error = |message: &str| {
+            Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                operator.clone(),
+                message.to_string(),
+            )))
+        };
+        let to_integer = |n: f64| {
+            (n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64).then_some(n as i64)
+        };
+
+        let (Some(left), Some(right)) = (to_integer(left), to_integer(right)) else {
+            return error("Operands must be integers.");
+        };
+
+        let result = match operator.ttype {
+            TokenType::Ampersand => left & right,
+            TokenType::Pipe => left | right,
+            TokenType::Caret => left ^ right,
+            TokenType::LessLess | TokenType::GreaterGreater if !(0..64).contains(&right) => {
+                return error("Shift amount must be between 0 and 63.")
+            }
+            // bits shifted past the top are dropped
+            TokenType::LessLess => left << right,
+            _ => left >> right,
+        };
+        Ok(Literal::Number(result as f64))
+    }
+
+    fn eval_unary(&mut self, u: &crate::parser::Unary) -> Result<Literal, RuntimeBreak> {
+        let right = self.evaluate(&u.right)?;
+        Interpreter::unary_op(&u.operator, right)
+    }
 
-        if u.operator.ttype == TokenType::Minus {
+    pub fn unary_op(operator: &Token, right: Literal) -> Result<Literal, RuntimeBreak> {
+        if operator.ttype == TokenType::Minus {
             if let Literal::Number(n) = right {
                 return Ok(Literal::Number(-n));
             } else {
-                return Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError {
-                    token: u.operator,
-                    message: "Operand must be number".to_string(),
-                }));
-            }
-        } else if u.operator.ttype == TokenType::Bang {
-            if let Literal::Bool(_) = right {
-                return Ok(Literal::Bool(!right.is_truthy()));
-            } else {
-                // unreachable as is_truthy() matches all types
-                return Ok(Literal::Null);
+                return Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                    operator.clone(),
+                    "Operand must be number".to_string(),
+                )));
             }
+        } else if operator.ttype == TokenType::Bang {
+            // every value is either truthy or falsey, so ! works on anything
+            return Ok(Literal::Bool(!right.is_truthy()));
         }
 
         // unreachable
         Ok(Literal::Null)
     }
 
-    fn is_equal(&self, left: Literal, right: Literal) -> bool {
-        if let (Literal::Null, Literal::Null) = (&left, &right) {
-            true
-        } else if let Literal::Null = left {
-            false
-        } else {
-            left == right
+    // what == compares by, also used by assert_eq
+    pub fn is_equal(left: Literal, right: Literal) -> bool {
+        match (&left, &right) {
+            (Literal::Null, Literal::Null) => true,
+            (Literal::Null, _) => false,
+            // IEEE 754, so NaN is not even equal to itself
+            (Literal::Number(l), Literal::Number(r)) => l == r,
+            _ => left == right,
         }
     }
 }
@@ -350,3 +1216,1050 @@ impl Default for Interpreter {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::Resolver;
+    use crate::scanner::Scanner;
+
+    // lets a test keep hold of the output written by the interpreter
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap().clone();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    // resolves and runs the source on an interpreter the test has set up, e.g. with limits or host functions
+    fn run_in(interpreter: &mut Interpreter, source: &str) -> Result<(), RuntimeBreak> {
+        let stmts = parse(source);
+        Resolver::new(interpreter).resolve(&stmts).unwrap();
+        interpreter.interpret(stmts)
+    }
+
+    fn run(source: &str) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        run_in(&mut interpreter, source).unwrap();
+        interpreter
+    }
+
+    fn runtime_error(source: &str) -> RuntimeError {
+        match run_in(&mut Interpreter::new(), source) {
+            Err(RuntimeBreak::RuntimeErrorBreak(re)) => re,
+            other => panic!("expected a runtime error, got {other:?}"),
+        }
+    }
+
+    fn global(interpreter: &Interpreter, name: &str) -> Literal {
+        let token = Token::new(TokenType::Identifier, name.to_string(), Literal::Null, 0, 0);
+        interpreter.globals.borrow().get(&token).unwrap()
+    }
+
+    #[test]
+    fn counter_closure_keeps_its_state() {
+        let interpreter = run("
+            fun makeCounter() {
+                var i = 0;
+                fun count() {
+                    i = i + 1;
+                    return i;
+                }
+                return count;
+            }
+            var counter = makeCounter();
+            var first = counter();
+            var second = counter();
+            var third = counter();
+        ");
+
+        assert_eq!(global(&interpreter, "first"), Literal::Number(1.0));
+        assert_eq!(global(&interpreter, "second"), Literal::Number(2.0));
+        assert_eq!(global(&interpreter, "third"), Literal::Number(3.0));
+    }
+
+    #[test]
+    fn closure_returned_from_method_keeps_this() {
+        let interpreter = run("
+            class Box {
+                getter() {
+                    fun get() {
+                        return this.value;
+                    }
+                    return get;
+                }
+            }
+            var box = Box();
+            box.value = \"inside\";
+            var get = box.getter();
+            box = nil;
+            var value = get();
+        ");
+
+        assert_eq!(
+            global(&interpreter, "value"),
+            Literal::String("inside".to_string())
+        );
+    }
+
+    #[test]
+    fn closure_sees_variable_from_its_declaration_scope() {
+        let interpreter = run("
+            var a = \"global\";
+            var first;
+            var second;
+            {
+                fun showA() {
+                    return a;
+                }
+                first = showA();
+                var a = \"block\";
+                second = showA();
+            }
+        ");
+
+        assert_eq!(
+            global(&interpreter, "first"),
+            Literal::String("global".to_string())
+        );
+        assert_eq!(
+            global(&interpreter, "second"),
+            Literal::String("global".to_string())
+        );
+    }
+
+    #[test]
+    fn continue_still_runs_for_loop_increment() {
+        let interpreter = run("
+            var iterations = 0;
+            var skipped = 0;
+            for (var i = 0; i < 10; i = i + 1) {
+                iterations = iterations + 1;
+                if (i < 4) {
+                    skipped = skipped + 1;
+                    continue;
+                }
+            }
+        ");
+
+        assert_eq!(global(&interpreter, "iterations"), Literal::Number(10.0));
+        assert_eq!(global(&interpreter, "skipped"), Literal::Number(4.0));
+    }
+
+    #[test]
+    fn break_exits_the_innermost_loop() {
+        let interpreter = run("
+            var iterations = 0;
+            for (var i = 0; i < 3; i = i + 1) {
+                while (true) {
+                    break;
+                }
+                iterations = iterations + 1;
+            }
+        ");
+
+        assert_eq!(global(&interpreter, "iterations"), Literal::Number(3.0));
+    }
+
+    #[test]
+    fn lambda_captures_its_environment() {
+        let interpreter = run("
+            fun makeAdder(n) {
+                return fun (x) { return x + n; };
+            }
+            var addTwo = makeAdder(2);
+            var result = addTwo(3);
+        ");
+
+        assert_eq!(global(&interpreter, "result"), Literal::Number(5.0));
+        assert_eq!(global(&interpreter, "addTwo").to_string(), "<fn anonymous>");
+    }
+
+    #[test]
+    fn ternary_is_right_associative_and_short_circuits() {
+        let interpreter = run("
+            fun fail() {
+                return undefined;
+            }
+            var nested = false ? 1 : true ? 2 : 3;
+            var skipped = true ? \"then\" : fail();
+        ");
+
+        assert_eq!(global(&interpreter, "nested"), Literal::Number(2.0));
+        assert_eq!(
+            global(&interpreter, "skipped"),
+            Literal::String("then".to_string())
+        );
+    }
+
+    #[test]
+    fn logical_operators_return_the_deciding_operand() {
+        let interpreter = run("
+            var orBoth = \"left\" or \"right\";
+            var orLeft = \"left\" or nil;
+            var orRight = nil or \"fallback\";
+            var orNeither = nil or false;
+            var andBoth = \"left\" and \"right\";
+            var andLeft = \"left\" and nil;
+            var andRight = nil and \"right\";
+            var andNeither = false and nil;
+        ");
+
+        let string = |s: &str| Literal::String(s.to_string());
+        assert_eq!(global(&interpreter, "orBoth"), string("left"));
+        assert_eq!(global(&interpreter, "orLeft"), string("left"));
+        assert_eq!(global(&interpreter, "orRight"), string("fallback"));
+        assert_eq!(global(&interpreter, "orNeither"), Literal::Bool(false));
+        assert_eq!(global(&interpreter, "andBoth"), string("right"));
+        assert_eq!(global(&interpreter, "andLeft"), Literal::Null);
+        assert_eq!(global(&interpreter, "andRight"), Literal::Null);
+        assert_eq!(global(&interpreter, "andNeither"), Literal::Bool(false));
+    }
+
+    #[test]
+    fn repl_echoes_a_trailing_expression() {
+        let buffer = SharedBuffer::default();
+        let mut scanner = Scanner::new("var a = 1; a = a + 1; print a; a * 10".to_string());
+        let tokens = scanner.scan_tokens().unwrap().clone();
+        let stmts = Parser::new(tokens).parse_repl().unwrap();
+
+        let mut interpreter = Interpreter::with_output(Box::new(buffer.clone()));
+        interpreter.interpret_repl(stmts).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buffer.0.borrow().clone()).unwrap(),
+            "2\n20\n"
+        );
+    }
+
+    #[test]
+    fn read_line_reads_from_the_injected_input() {
+        let buffer = SharedBuffer::default();
+        let source = "print read_line(); print read_line(); print read_line();";
+        let input = "first line\r\nsecond\n".as_bytes();
+        let mut interpreter = Interpreter::with_io(Box::new(input), Box::new(buffer.clone()));
+        run_in(&mut interpreter, source).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buffer.0.borrow().clone()).unwrap(),
+            "first line\nsecond\nnil\n"
+        );
+    }
+
+    #[test]
+    fn args_returns_the_script_arguments() {
+        let mut interpreter = Interpreter::new();
+        interpreter.script_args = vec!["first".to_string(), "two words".to_string()];
+        interpreter.define_native("len_of", 1, |_, args| match args[0] {
+            Literal::List(ref list) => Ok(Literal::Number(list.borrow().len() as f64)),
+            _ => Ok(Literal::Null),
+        });
+        run_in(&mut interpreter, "var a = args(); var n = len_of(a);").unwrap();
+
+        assert_eq!(global(&interpreter, "a").to_string(), "[first, two words]");
+        assert_eq!(global(&interpreter, "n"), Literal::Number(2.0));
+        assert_eq!(global(&run("var a = args();"), "a").to_string(), "[]");
+    }
+
+    // writes the files to a fresh directory and runs the first one as the script
+    fn run_files(test: &str, files: &[(&str, &str)]) -> Result<Interpreter, RuntimeBreak> {
+        let dir = std::env::temp_dir().join(format!("lox_{test}_{}", std::process::id()));
+        for (name, source) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+
+        let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+        interpreter.script_path = Some(dir.join(files[0].0));
+        let result = run_in(&mut interpreter, files[0].1);
+        fs::remove_dir_all(dir).unwrap();
+        result.map(|()| interpreter)
+    }
+
+    #[test]
+    fn imports_run_in_the_global_scope_relative_to_the_importer() {
+        let interpreter = run_files(
+            "imports",
+            &[
+                (
+                    "main.lox",
+                    "{ import \"lib/util.lox\"; }\nvar x = double(21);",
+                ),
+                (
+                    "lib/util.lox",
+                    "import \"helper.lox\";\nfun double(n) { return helper(n) * 2; }",
+                ),
+                ("lib/helper.lox", "fun helper(n) { return n; }"),
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(global(&interpreter, "x"), Literal::Number(42.0));
+    }
+
+    #[test]
+    fn import_errors_point_at_the_import() {
+        let message_and_line = |result: Result<Interpreter, RuntimeBreak>| match result {
+            Err(RuntimeBreak::RuntimeErrorBreak(re)) => (re.message, re.token.line),
+            _ => panic!("expected a runtime error"),
+        };
+
+        let missing = run_files(
+            "missing",
+            &[("main.lox", "var a = 1;\nimport \"nope.lox\";")],
+        );
+        assert_eq!(
+            message_and_line(missing),
+            ("Could not read imported file 'nope.lox'.".to_string(), 2)
+        );
+
+        let cycle = run_files("cycle", &[("main.lox", "\nimport \"main.lox\";")]);
+        assert_eq!(
+            message_and_line(cycle),
+            ("Import cycle through 'main.lox'.".to_string(), 2)
+        );
+
+        let broken = run_files(
+            "broken",
+            &[
+                ("main.lox", "\nimport \"bad.lox\";"),
+                ("bad.lox", "print );"),
+            ],
+        );
+        let (message, line) = message_and_line(broken);
+        assert!(message.starts_with("Error in 'bad.lox': "), "{message}");
+        assert_eq!(line, 2);
+    }
+
+    #[test]
+    fn errors_in_imported_code_show_the_imported_file() {
+        let error = |test: &str, main: &str, lib: &str| match run_files(
+            test,
+            &[("main.lox", main), ("lib.lox", lib)],
+        ) {
+            Err(err @ RuntimeBreak::RuntimeErrorBreak(_)) => err,
+            _ => panic!("expected a runtime error"),
+        };
+        let lib = "print \"lib line one\";\nprint nil + 1;\nfun f() {\n  return -\"a\";\n}\nfun g(h) { return h(); }";
+
+        let top_level = error(
+            "top_level",
+            "import \"lib.lox\";\nprint \"main line two\";",
+            lib,
+        );
+        assert!(
+            top_level
+                .to_string()
+                .ends_with("[line 2, col 11 in 'lib.lox']"),
+            "{top_level}"
+        );
+        let (line, column, width) = top_level.span().unwrap();
+        let snippet = render_snippet(top_level.file_source().unwrap(), line, column, width);
+        assert!(snippet.starts_with("2 | print nil + 1;"), "{snippet}");
+
+        // a function keeps its file however late it's called
+        let lib = &lib.replace("print nil + 1;", "");
+        let called = error("called", "import \"lib.lox\";\nf();", lib);
+        assert!(
+            called.to_string().contains("[line 4, col 10 in 'lib.lox']"),
+            "{called}"
+        );
+        assert!(called.file_source().unwrap().contains("fun f()"));
+
+        // while a function from the program called by the imported one is back in the program
+        let callback = error(
+            "callback",
+            "import \"lib.lox\";\ng(fun () { return -nil; });",
+            lib,
+        );
+        assert!(callback.file_source().is_none(), "{callback}");
+        assert!(
+            callback.to_string().contains("[line 2, col 19]"),
+            "{callback}"
+        );
+    }
+
+    #[test]
+    fn host_functions_can_be_called_from_scripts() {
+        let buffer = SharedBuffer::default();
+        let mut interpreter = Interpreter::with_output(Box::new(buffer.clone()));
+        interpreter.define_native("double", 1, |_, args| match args[0] {
+            Literal::Number(n) => Ok(Literal::Number(n * 2.0)),
+            _ => Ok(Literal::Null),
+        });
+        run_in(&mut interpreter, "print double(21); print double;").unwrap();
+
+        assert_eq!(
+            String::from_utf8(buffer.0.borrow().clone()).unwrap(),
+            "42\n<native fn double>\n"
+        );
+    }
+
+    #[test]
+    fn every_kind_of_callable_is_called_the_same_way() {
+        let source = "
+            fun add(a, b) { return a + b; }
+            class Point { sum() { return add(1, 2); } }
+            var f = add(1, 2);
+            var l = (fun (a) { return a; })(3);
+            var n = sqrt(9);
+            var h = triple(1);
+            var m = Point().sum();
+            var same = add == add and sqrt == sqrt and triple == triple;
+            var different = add != sqrt and sqrt != abs and Point().sum != Point().sum;
+        ";
+        let mut interpreter = Interpreter::new();
+        interpreter.define_native("triple", 1, |_, _| Ok(Literal::Number(3.0)));
+        run_in(&mut interpreter, source).unwrap();
+
+        for name in ["f", "l", "n", "h", "m"] {
+            assert_eq!(global(&interpreter, name), Literal::Number(3.0), "{name}");
+        }
+        assert_eq!(global(&interpreter, "same"), Literal::Bool(true));
+        assert_eq!(global(&interpreter, "different"), Literal::Bool(true));
+
+        let calls = [
+            ("fun f(a) {} f();", "Expected 1 arguments but got 0"),
+            (
+                "var g = fun (a) {}; g(1, 2);",
+                "Expected 1 arguments but got 2",
+            ),
+            ("sqrt();", "Expected 1 arguments but got 0"),
+            ("class C {} C(1);", "Expected 0 arguments but got 1"),
+            (
+                "class C { m(a) {} } C().m();",
+                "Expected 1 arguments but got 0",
+            ),
+        ];
+        for (source, message) in calls {
+            assert_eq!(runtime_error(source).message, message, "{source}");
+        }
+    }
+
+    #[test]
+    fn locals_are_found_by_their_slot_in_each_scope() {
+        let interpreter = run("
+            var result;
+            {
+                var a = \"outer a\";
+                var b = \"outer b\";
+                {
+                    var b = \"inner b\";
+                    var c = a;
+                    var a = \"inner a\";
+                    b = b + \", \" + c + \", \" + a;
+                    result = b;
+                }
+                fun pair(x, y = x + \"!\", ...rest) {
+                    var z = y;
+                    return [a, b, x, z, rest];
+                }
+                class Box { init(v) { this.v = v; } get() { return this.v + b; } }
+                var box = Box(a);
+                result = [result, pair(\"x\"), pair(\"x\", \"y\", 1, 2), box.get()];
+            }
+        ");
+        assert_eq!(
+            global(&interpreter, "result").to_string(),
+            "[inner b, outer a, inner a, [outer a, outer b, x, x!, []], [outer a, outer b, x, y, [1, 2]], outer aouter b]"
+        );
+    }
+
+    #[test]
+    fn failed_assertions_are_runtime_errors() {
+        let failures = [
+            ("assert(false);", "Assertion failed."),
+            ("assert(nil);", "Assertion failed."),
+            ("assert_eq(1, \"1\");", "Assertion failed: 1 != \"1\"."),
+            (
+                "assert_eq(nil, \"nil\");",
+                "Assertion failed: nil != \"nil\".",
+            ),
+            ("assert_eq([1], [2]);", "Assertion failed: [1] != [2]."),
+            ("assert_eq(0 / 0, 0 / 0);", "Assertion failed: NaN != NaN."),
+        ];
+        for (source, message) in failures {
+            assert_eq!(runtime_error(source).message, message, "{source}");
+        }
+    }
+
+    #[test]
+    fn classes_take_the_arity_of_init() {
+        let interpreter = run("
+            class A { init(a, b) { this.sum = a + b; } }
+            class B < A {}
+            var a = A(1, 2).sum;
+            var b = B(3, 4).sum;
+            var c = A(1, 2).init(5, 6).sum;
+        ");
+        assert_eq!(global(&interpreter, "a"), Literal::Number(3.0));
+        assert_eq!(global(&interpreter, "b"), Literal::Number(7.0));
+        assert_eq!(global(&interpreter, "c"), Literal::Number(11.0));
+
+        let calls = [
+            (
+                "class A { init(a) {} } A();",
+                "Expected 1 arguments but got 0",
+            ),
+            (
+                "class A { init(a) {} } A(1, 2);",
+                "Expected 1 arguments but got 2",
+            ),
+            (
+                "class A { init(a, b = 1) {} } A();",
+                "Expected 1 to 2 arguments but got 0",
+            ),
+            (
+                "class A {} class B < A {} B(1);",
+                "Expected 0 arguments but got 1",
+            ),
+        ];
+        for (source, message) in calls {
+            assert_eq!(runtime_error(source).message, message, "{source}");
+        }
+    }
+
+    #[test]
+    fn clocks_count_up_from_interpreter_start() {
+        let interpreter = run("
+            var seconds = clock();
+            var millis = clock_millis();
+            var later = clock();
+        ");
+
+        let number = |name| match global(&interpreter, name) {
+            Literal::Number(n) => n,
+            other => panic!("expected a number, got {other:?}"),
+        };
+        let elapsed = interpreter.elapsed().as_secs_f64();
+        assert!(0.0 <= number("seconds") && number("seconds") <= number("later"));
+        assert!(number("later") <= elapsed);
+        assert!(number("millis") >= number("seconds") * 1000.0);
+        assert!(number("millis") <= elapsed * 1000.0);
+    }
+
+    #[test]
+    fn numbers_have_double_precision() {
+        let interpreter = run("
+            var a = 9007199254740992 + 1;
+            var b = 16777216 + 1;
+            var c = 0.1 + 0.2;
+            var d = 123456789 * 1000;
+        ");
+
+        assert_eq!(
+            global(&interpreter, "a"),
+            Literal::Number(9007199254740992.0)
+        );
+        assert_eq!(global(&interpreter, "b"), Literal::Number(16777217.0));
+        assert_eq!(global(&interpreter, "c"), Literal::Number(0.1 + 0.2));
+        assert_eq!(global(&interpreter, "d"), Literal::Number(123456789000.0));
+    }
+
+    #[test]
+    fn runtime_errors_carry_a_stack_trace() {
+        let source = "fun inner() {\n  return nil + 1;\n}\nfun middle() {\n  return inner();\n}\nfun outer() {\n  middle();\n}\nouter();";
+        let mut interpreter = Interpreter::new();
+        let err = run_in(&mut interpreter, source).unwrap_err();
+
+        let RuntimeBreak::RuntimeErrorBreak(ref re) = err else {
+            panic!("expected a runtime error, got {err:?}");
+        };
+        let frames: Vec<(&str, u32)> = re
+            .trace
+            .iter()
+            .map(|frame| (frame.function.as_str(), frame.line))
+            .collect();
+        assert_eq!(frames, [("inner", 5), ("middle", 8), ("outer", 10)]);
+        assert_eq!(
+            err.to_string(),
+            "Runtime error at Plus: Operands must be two numbers or two strings. [line 2, col 14]\n  in inner() called from line 5\n  in middle() called from line 8\n  in outer() called from line 10"
+        );
+        assert!(interpreter.call_stack.is_empty());
+    }
+
+    #[test]
+    fn unbounded_recursion_is_a_runtime_error() {
+        // a low limit, as test threads only get a couple of megabytes of stack
+        let mut interpreter = Interpreter::new();
+        interpreter.max_call_depth = 100;
+        let err = run_in(&mut interpreter, "fun f(n) { return f(n + 1); } f(0);").unwrap_err();
+
+        let RuntimeBreak::RuntimeErrorBreak(re) = err else {
+            panic!("expected a runtime error, got {err:?}");
+        };
+        assert_eq!(re.message, "Stack overflow.");
+        assert_eq!(re.trace.len(), 100);
+        assert!(interpreter.call_stack.is_empty());
+    }
+
+    #[test]
+    fn step_limit_stops_runaway_scripts() {
+        let limited = |source: &str| run_in(&mut Interpreter::with_step_limit(1000), source);
+
+        let err = limited("while (true) {}").unwrap_err();
+        let RuntimeBreak::RuntimeErrorBreak(re) = err else {
+            panic!("expected a runtime error, got {err:?}");
+        };
+        assert_eq!(re.message, "Execution step limit exceeded.");
+        assert_eq!(re.token.ttype, TokenType::While);
+
+        let err = limited("fun f(n) { if (n > 0) { f(n - 1); f(n - 1); } } f(30);").unwrap_err();
+        assert!(err.to_string().contains("Execution step limit exceeded."));
+
+        assert!(limited("for (var i = 0; i < 10; i = i + 1) {}").is_ok());
+        assert!(Interpreter::new().step_limit.is_none());
+    }
+
+    #[test]
+    fn a_fixed_seed_gives_a_reproducible_sequence() {
+        let source = "
+            seed(42);
+            var rolls = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+            for (var i = 0; i < 10; i = i + 1) rolls[i] = random_int(1, 6);
+            var r = random();
+        ";
+        let first = run(source);
+        let second = run(source);
+
+        assert_eq!(global(&first, "rolls"), global(&second, "rolls"));
+        assert_eq!(global(&first, "r"), global(&second, "r"));
+
+        let Literal::List(rolls) = global(&first, "rolls") else {
+            panic!("expected a list");
+        };
+        for roll in rolls.borrow().iter() {
+            let Literal::Number(n) = *roll else {
+                panic!("expected a number, got {roll:?}");
+            };
+            assert!((1.0..=6.0).contains(&n) && n.fract() == 0.0, "{n}");
+        }
+        let Literal::Number(r) = global(&first, "r") else {
+            panic!("expected a number");
+        };
+        assert!((0.0..1.0).contains(&r));
+
+        // seeding from the embedder gives the same sequence as seeding from the script
+        let script = run("seed(7); var a = random();");
+        let mut embedder = Interpreter::new();
+        embedder.seed(7);
+        let expected = (embedder.next_random() >> 11) as f64 / (1u64 << 53) as f64;
+        assert_eq!(global(&script, "a"), Literal::Number(expected));
+        assert_eq!(
+            runtime_error("random_int(3, 1);").message,
+            "Lower bound to 'random_int' can't be greater than the upper bound."
+        );
+    }
+
+    #[test]
+    fn sandboxed_interpreter_only_has_safe_natives() {
+        let sandboxed = |source: &str| run_in(&mut Interpreter::new_sandboxed(), source);
+
+        let err = sandboxed("import \"lib.lox\";").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Can't import in a sandboxed interpreter."));
+
+        for source in ["clock();", "clock_millis();", "read_line();"] {
+            let err = sandboxed(source).unwrap_err();
+            let RuntimeBreak::RuntimeErrorBreak(re) = err else {
+                panic!("expected a runtime error, got {err:?}");
+            };
+            assert!(re.message.starts_with("Undefined variable"), "{source}");
+        }
+        assert!(sandboxed("var a = sqrt(4) + abs(-1); var s = str(a);").is_ok());
+    }
+
+    #[test]
+    fn stack_traces_are_capped() {
+        let source = "fun f(n) { if (n == 0) return nil + 1; f(n - 1); } f(20);";
+        let err = run_in(&mut Interpreter::new(), source)
+            .unwrap_err()
+            .to_string();
+
+        assert_eq!(err.lines().count(), 12);
+        assert!(err.ends_with("  ... 11 more"));
+    }
+
+    #[test]
+    fn undefined_variables_suggest_names_from_enclosing_scopes() {
+        assert_eq!(
+            runtime_error("fun f(length) { return lenght; } f(1);").message,
+            "Undefined variable 'lenght'. Did you mean 'length'?"
+        );
+        assert_eq!(
+            runtime_error("var total = 0; totl = 1;").message,
+            "Undefined variable 'totl'. Did you mean 'total'?"
+        );
+    }
+
+    #[test]
+    fn properties_only_exist_on_instances() {
+        let get = runtime_error("var n = 3;\nprint n.x;");
+        assert_eq!(get.message, "Only instances have properties.");
+        assert_eq!(
+            (get.token.lexeme.as_ref(), get.token.line, get.token.column),
+            ("x", 2, 9)
+        );
+
+        let set = runtime_error("\"str\".length = 1;");
+        assert_eq!(set.message, "Only instances have properties.");
+        assert_eq!(
+            (set.token.lexeme.as_ref(), set.token.line, set.token.column),
+            ("length", 1, 7)
+        );
+
+        let call = runtime_error("var n = 3;\nn();");
+        assert_eq!(call.message, "Can only call functions and classes");
+        assert_eq!(call.token.line, 2);
+    }
+
+    #[test]
+    fn bitwise_operators_work_on_integers() {
+        let interpreter = run("
+            var low = 1234 & 255;
+            var set = 8 | 3;
+            var flipped = 6 ^ 3;
+            var shifted = 1 << 10;
+            var halved = 1024 >> 3;
+            var negative = -1 & 255;
+            var signed = -8 >> 1;
+            var top = 1 << 63;
+            var termFirst = 1 + 2 << 1;
+            var shiftFirst = 1 << 2 < 5;
+            var andFirst = 1 | 2 & 3;
+        ");
+
+        assert_eq!(global(&interpreter, "low"), Literal::Number(210.0));
+        assert_eq!(global(&interpreter, "set"), Literal::Number(11.0));
+        assert_eq!(global(&interpreter, "flipped"), Literal::Number(5.0));
+        assert_eq!(global(&interpreter, "shifted"), Literal::Number(1024.0));
+        assert_eq!(global(&interpreter, "halved"), Literal::Number(128.0));
+        assert_eq!(global(&interpreter, "negative"), Literal::Number(255.0));
+        assert_eq!(global(&interpreter, "signed"), Literal::Number(-4.0));
+        assert_eq!(
+            global(&interpreter, "top"),
+            Literal::Number(i64::MIN as f64)
+        );
+        assert_eq!(global(&interpreter, "termFirst"), Literal::Number(6.0));
+        assert_eq!(global(&interpreter, "shiftFirst"), Literal::Bool(true));
+        assert_eq!(global(&interpreter, "andFirst"), Literal::Number(3.0));
+    }
+
+    #[test]
+    fn var_declares_each_variable_in_order() {
+        let interpreter = run("
+            var a = 1, b, c = a + 1;
+            var steps = 0;
+            for (var i = 0, j = 3; i < j; i = i + 1) {
+                var doubled = i * 2, sum = doubled + j;
+                steps = steps + sum;
+            }
+        ");
+
+        assert_eq!(global(&interpreter, "a"), Literal::Number(1.0));
+        assert_eq!(global(&interpreter, "b"), Literal::Null);
+        assert_eq!(global(&interpreter, "c"), Literal::Number(2.0));
+        assert_eq!(global(&interpreter, "steps"), Literal::Number(15.0));
+    }
+
+    #[test]
+    fn exponent_is_right_associative() {
+        let interpreter = run("
+            var nested = 2 ** 3 ** 2;
+            var grouped = (2 ** 3) ** 2;
+            var product = 2 * 3 ** 2;
+            var negated = -2 ** 2;
+            var root = 9 ** 0.5;
+            var inverse = 2 ** -1;
+        ");
+
+        assert_eq!(global(&interpreter, "nested"), Literal::Number(512.0));
+        assert_eq!(global(&interpreter, "grouped"), Literal::Number(64.0));
+        assert_eq!(global(&interpreter, "product"), Literal::Number(18.0));
+        assert_eq!(global(&interpreter, "negated"), Literal::Number(4.0));
+        assert_eq!(global(&interpreter, "root"), Literal::Number(3.0));
+        assert_eq!(global(&interpreter, "inverse"), Literal::Number(0.5));
+        assert_eq!(
+            runtime_error("\"a\" ** 2;").message,
+            "Invalid operator used with a string and a number"
+        );
+    }
+
+    #[test]
+    fn runtime_errors_point_at_the_line_they_happen_on() {
+        // each error is on the third line, after something valid on the first
+        let cases = [
+            "var a = 1;\n\n-\"a\";",
+            "var a = 1;\n\na + nil;",
+            "var a = 1;\n\n\"a\" * -1;",
+            "var a = 1;\n\nb;",
+            "var a = 1;\n\nb = 2;",
+            "var a = 1;\n\na();",
+            "fun f() {}\n\nf(1);",
+            "var a = 1;\n\na.field;",
+            "var a = 1;\n\na.field = 2;",
+            "class A {}\n\nA().missing;",
+            "class A {}\nclass B < A {\n  m() { return super.missing; }\n}\nB().m();",
+            "var a = 1;\n\nclass B < a {}",
+            "var a = 1;\n\na[0];",
+            "var a = [1];\n\na[5];",
+            "var a = \"s\";\n\na[0] = \"t\";",
+            "var a = 1;\n\nfor (x in a) {}",
+            "var a = 1;\n\nsqrt(\"a\");",
+            "var a = 1;\n\n1.5 | 1;",
+        ];
+
+        for source in cases {
+            let err = runtime_error(source);
+            assert_eq!(err.token.line, 3, "{source}: {}", err.message);
+        }
+    }
+
+    #[test]
+    fn host_function_errors_point_at_the_call() {
+        let mut interpreter = Interpreter::new();
+        interpreter.define_native("fail", 0, |_, _| {
+            let token = Token::new(
+                TokenType::Identifier,
+                "fail".to_string(),
+                Literal::Null,
+                0,
+                0,
+            );
+            Err(RuntimeBreak::RuntimeErrorBreak(RuntimeError::new(
+                token,
+                "Host failure.".to_string(),
+            )))
+        });
+        let err = run_in(&mut interpreter, "var a = 1;\n\nfail();").unwrap_err();
+
+        assert!(
+            err.to_string().ends_with("Host failure. [line 3, col 6]"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn unresolved_super_is_a_runtime_error_rather_than_a_panic() {
+        // the resolver rejects this, but an embedder can skip resolving
+        let stmts = parse("fun f(x) { return super.m; } f(1);");
+
+        match Interpreter::new().interpret(stmts) {
+            Err(RuntimeBreak::RuntimeErrorBreak(re)) => {
+                assert_eq!(re.message, "Can't use 'super' outside of a class.");
+                assert_eq!(re.token.column, 19);
+            }
+            other => panic!("expected a runtime error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn return_escaping_to_the_top_level_is_a_runtime_error() {
+        // the parser won't produce this, but a tree built by hand can
+        let keyword = Token::new(TokenType::Return, "return".to_string(), Literal::Null, 1, 1);
+        let stmts = vec![Stmt::ReturnStmt(Return {
+            keyword,
+            value: Expr::LitExpr(Literal::Number(1.0)),
+        })];
+
+        let err = Interpreter::new().interpret(stmts).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Runtime error at Return: Can't return from top-level code. [line 1, col 1]"
+        );
+    }
+
+    #[test]
+    fn getters_cant_be_called() {
+        let source = "
+            class A { f { return fun () { return 1; }; } }
+            class B < A { g() { return super.f(); } }
+            B().g();
+        ";
+        let err = runtime_error(source);
+        assert_eq!(err.message, "Getter 'f' can't be called.");
+        assert_eq!(err.token.line, 3);
+
+        // a field shadowing the getter can be called as usual
+        let interpreter = run("
+            class A { f { return 1; } }
+            var a = A();
+            a.f = fun () { return 2; };
+            var called = a.f();
+        ");
+        assert_eq!(global(&interpreter, "called"), Literal::Number(2.0));
+    }
+
+    #[test]
+    fn functions_are_only_equal_to_themselves() {
+        let interpreter = run("
+            fun f() {}
+            var g = f;
+            var same = f == g;
+            fun make() { return fun () {}; }
+            var closures = make() == make();
+            class A { m() {} }
+            var a = A();
+            var bound = a.m;
+            var rebound = a.m == a.m;
+            var kept = bound == bound;
+            fun h() {}
+            var first = h;
+            fun h() {}
+            var redeclared = first == h;
+        ");
+
+        assert_eq!(global(&interpreter, "same"), Literal::Bool(true));
+        assert_eq!(global(&interpreter, "closures"), Literal::Bool(false));
+        assert_eq!(global(&interpreter, "rebound"), Literal::Bool(false));
+        assert_eq!(global(&interpreter, "kept"), Literal::Bool(true));
+        // the same source in the same scope is still a different function
+        assert_eq!(global(&interpreter, "redeclared"), Literal::Bool(false));
+    }
+
+    #[test]
+    fn nan_is_not_equal_to_itself() {
+        let interpreter = run("
+            var x = 0 / 0;
+            var same = x == x;
+            var different = x != x;
+            var literal = (0 / 0) == (0 / 0);
+        ");
+
+        assert_eq!(global(&interpreter, "same"), Literal::Bool(false));
+        assert_eq!(global(&interpreter, "different"), Literal::Bool(true));
+        assert_eq!(global(&interpreter, "literal"), Literal::Bool(false));
+    }
+
+    #[test]
+    fn strings_never_equal_numbers() {
+        let interpreter = run("
+            var same = \"1\" == 1;
+            var flipped = 1 == \"1\";
+            var different = \"1\" != 1;
+            var joined = \"1\" + 1;
+        ");
+
+        assert_eq!(global(&interpreter, "same"), Literal::Bool(false));
+        assert_eq!(global(&interpreter, "flipped"), Literal::Bool(false));
+        assert_eq!(global(&interpreter, "different"), Literal::Bool(true));
+        assert_eq!(
+            global(&interpreter, "joined"),
+            Literal::String("11".to_string())
+        );
+    }
+
+    #[test]
+    fn strings_compare_lexicographically() {
+        let interpreter = run("
+            var fruit = \"apple\" < \"banana\";
+            var prefix = \"ab\" < \"abc\";
+            var longer = \"abc\" > \"ab\";
+            var equal = \"ab\" <= \"ab\" and \"ab\" >= \"ab\";
+            var strict = \"ab\" < \"ab\";
+            var cased = \"Z\" < \"a\";
+        ");
+
+        assert_eq!(global(&interpreter, "fruit"), Literal::Bool(true));
+        assert_eq!(global(&interpreter, "prefix"), Literal::Bool(true));
+        assert_eq!(global(&interpreter, "longer"), Literal::Bool(true));
+        assert_eq!(global(&interpreter, "equal"), Literal::Bool(true));
+        assert_eq!(global(&interpreter, "strict"), Literal::Bool(false));
+        assert_eq!(global(&interpreter, "cased"), Literal::Bool(true));
+        assert_eq!(
+            runtime_error("\"1\" < 2;").message,
+            "Invalid operator used with a string and a number"
+        );
+    }
+
+    #[test]
+    fn strings_repeat_a_whole_number_of_times() {
+        let interpreter = run("
+            var dashes = \"-\" * 5;
+            var flipped = 3 * \"ab\";
+            var none = \"ab\" * 0;
+        ");
+
+        assert_eq!(
+            global(&interpreter, "dashes"),
+            Literal::String("-----".to_string())
+        );
+        assert_eq!(
+            global(&interpreter, "flipped"),
+            Literal::String("ababab".to_string())
+        );
+        assert_eq!(global(&interpreter, "none"), Literal::String(String::new()));
+        for source in ["\"-\" * -5;", "\"-\" * 1.5;", "(0 / 0) * \"-\";"] {
+            assert_eq!(
+                runtime_error(source).message,
+                "A string can only be repeated a whole, non-negative number of times."
+            );
+        }
+        for source in ["\"ab\" * 1e300;", "\"a\" * 10000000000;"] {
+            assert_eq!(
+                runtime_error(source).message,
+                "Repeated string is too long."
+            );
+        }
+        let longest = format!("\"ab\" * {};", MAX_REPEAT_LENGTH / 2 + 1);
+        assert_eq!(
+            runtime_error(&longest).message,
+            "Repeated string is too long."
+        );
+        let interpreter = run(&format!("var a = \"ab\" * {};", MAX_REPEAT_LENGTH / 2));
+        let Literal::String(a) = global(&interpreter, "a") else {
+            panic!("expected a string");
+        };
+        assert_eq!(a.len(), MAX_REPEAT_LENGTH);
+        // an empty string stays empty however many times it's repeated
+        let interpreter = run("var e = \"\" * 1e300;");
+        assert_eq!(global(&interpreter, "e"), Literal::String(String::new()));
+    }
+
+    #[test]
+    fn bitwise_operators_reject_fractions_and_bad_shifts() {
+        assert_eq!(
+            runtime_error("1.5 & 1;").message,
+            "Operands must be integers."
+        );
+        assert_eq!(
+            runtime_error("1 | 0.5;").message,
+            "Operands must be integers."
+        );
+        assert_eq!(
+            runtime_error("1 << 64;").message,
+            "Shift amount must be between 0 and 63."
+        );
+        assert_eq!(
+            runtime_error("1 >> -1;").message,
+            "Shift amount must be between 0 and 63."
+        );
+    }
+
+    #[test]
+    fn print_writes_to_the_injected_output() {
+        let buffer = SharedBuffer::default();
+        let mut interpreter = Interpreter::with_output(Box::new(buffer.clone()));
+        run_in(&mut interpreter, "print 1; print \"two\"; print nil;").unwrap();
+
+        assert_eq!(
+            String::from_utf8(buffer.0.borrow().clone()).unwrap(),
+            "1\ntwo\nnil\n"
+        );
+    }
+}
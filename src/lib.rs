@@ -0,0 +1,24 @@
+// A Rust implementation of the tree-walk interpreter from Crafting Interpreters
+// main.rs is a thin binary over this library, which tools and tests can use directly
+
+pub mod token;
+
+pub mod scanner;
+
+pub mod error;
+
+pub mod lox;
+
+pub mod interpreter;
+
+pub mod parser;
+
+pub mod ast_printer;
+
+pub mod optimizer;
+
+pub mod resolver;
+
+pub mod environment;
+
+pub mod callable;
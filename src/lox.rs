@@ -1,129 +1,559 @@
-use crate::exit;
+use crate::ast_printer;
+use crate::error::{render_snippet, Error, RuntimeBreak, SourceError};
 use crate::interpreter::Interpreter;
-use crate::io;
-use crate::parser::Parser;
-use crate::stdin;
-use crate::stdout;
-use crate::File;
-use crate::Rc;
-use crate::RuntimeBreak;
-use crate::Scanner;
-use std::error::Error;
+use crate::optimizer;
+use crate::parser::{Parser, Stmt};
+use crate::resolver::Resolver;
+use crate::scanner::Scanner;
+use crate::token::TokenType;
+use std::fs::File;
+use std::io::{self, stdin, stdout};
 use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// what to do with a script given on the command line
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Run,
+    DumpTokens,
+    DumpAst,
+}
+
+// why a run of the command line interpreter failed, which main turns into an exit code
+#[derive(Debug)]
+pub enum Failure {
+    Usage,
+    Scan,
+    Parse,
+    Resolve,
+    Runtime,
+    // the script called exit, which isn't an error but still ends the run early
+    Exit(i32),
+    Io(io::Error),
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Self {
+        Failure::Io(err)
+    }
+}
+
+impl From<&Error> for Failure {
+    fn from(err: &Error) -> Self {
+        match err {
+            Error::Scan(_) => Failure::Scan,
+            Error::Parse(_) => Failure::Parse,
+            Error::Resolve(_) => Failure::Resolve,
+            Error::Runtime(RuntimeBreak::ExitBreak(code)) => Failure::Exit(*code),
+            Error::Runtime(_) => Failure::Runtime,
+        }
+    }
+}
 
 pub struct Lox {
     args: Rc<[String]>,
     interpreter: Interpreter,
-    had_error: bool,
-    had_runtime_error: bool,
+    // fold constant expressions before running or dumping the program
+    optimize: bool,
 }
 
 impl Lox {
-    pub fn new(args: Rc<[String]>) -> Result<Self, io::Error> {
+    pub fn new(args: Rc<[String]>) -> Result<Self, Failure> {
         // decide to run a script or trigger prompt
 
-        let mut i = Self {
-            args,
-            interpreter: Interpreter::new(),
-            had_error: false,
-            had_runtime_error: false,
-        };
-
-        let len = i.args.len();
+        let mut i = Self::with_args(args);
 
-        match len {
-            2 => {
-                i.run_file(i.args[1].clone())?;
-            }
-            _ => {
-                if len > 2 {
-                    println!("Usage: rlox [script]");
-                    exit(64);
-                } else {
-                    let _prompt = &i.run_prompt()?;
+        // flags come before the script path, anything after it is passed to the script
+        let mut mode = Mode::Run;
+        let mut script: Option<String> = None;
+        let argv = Rc::clone(&i.args);
+        let mut rest = argv.iter().skip(1);
+        for arg in rest.by_ref() {
+            match arg.as_str() {
+                "--dump-tokens" => mode = Mode::DumpTokens,
+                "--dump-ast" => mode = Mode::DumpAst,
+                "--optimize" => i.optimize = true,
+                "--trace" => i.interpreter.trace = true,
+                flag if flag.starts_with("--") => return Err(Lox::usage()),
+                _ => {
+                    script = Some(arg.clone());
+                    break;
                 }
             }
+        }
+        i.interpreter.script_args = rest.cloned().collect();
+
+        match (script, mode) {
+            (None, Mode::Run) => i.run_prompt()?,
+            (Some(path), Mode::Run) => i.run_file(&path)?,
+            (Some(path), Mode::DumpTokens) => i.dump_tokens(&path)?,
+            (Some(path), Mode::DumpAst) => i.dump_ast(&path)?,
+            _ => return Err(Lox::usage()),
         };
 
         Ok(i)
     }
 
-    fn run_file(&mut self, path: String) -> Result<String, io::Error> {
-        // read contents of file and run it
-        let mut file = File::open(path)?;
+    fn with_args(args: Rc<[String]>) -> Self {
+        Lox::with_interpreter(args, Interpreter::new())
+    }
+
+    fn with_interpreter(args: Rc<[String]>, interpreter: Interpreter) -> Self {
+        Self {
+            args,
+            interpreter,
+            optimize: false,
+        }
+    }
+
+    fn usage() -> Failure {
+        println!("Usage: rlox [--dump-tokens | --dump-ast] [--optimize] [--trace] [script | -] [args...]");
+        Failure::Usage
+    }
+
+    // a path of "-" reads the whole of stdin instead, so programs can be piped in
+    fn read_file(path: &str) -> Result<String, io::Error> {
         let mut contents = String::new();
+        if path == "-" {
+            stdin().read_to_string(&mut contents)?;
+            return Ok(contents);
+        }
+
+        let mut file = File::open(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("Could not open file: {path}")))?;
         file.read_to_string(&mut contents)?;
-        Lox::run(self, contents.as_str());
+        Ok(contents)
+    }
 
-        if self.had_error {
-            exit(65);
-        } else if self.had_runtime_error {
-            exit(70);
+    // read contents of file and run it, failing the same way whether or not the source came from stdin
+    fn run_file(&mut self, path: &str) -> Result<(), Failure> {
+        let contents = Lox::read_file(path)?;
+        // a script read from stdin imports relative to the working directory
+        if path != "-" {
+            self.interpreter.script_path = Some(PathBuf::from(path));
         }
+        Lox::run(self, contents.as_str())
+    }
 
-        Ok(contents)
+    // prints every token in the file without parsing or running it
+    fn dump_tokens(&self, path: &str) -> Result<(), Failure> {
+        let contents = Lox::read_file(path)?;
+        let mut scanner = Scanner::new(contents.clone());
+
+        match scanner.scan_tokens() {
+            Ok(tokens) => {
+                for token in tokens {
+                    println!("{token}");
+                }
+            }
+            Err(errors) => {
+                for err in errors {
+                    Lox::print_error(&err, &contents);
+                }
+                return Err(Failure::Scan);
+            }
+        }
+        Ok(())
+    }
+
+    // prints the parsed program as s-expressions without resolving or running it
+    fn dump_ast(&self, path: &str) -> Result<(), Failure> {
+        let contents = Lox::read_file(path)?;
+        let mut scanner = Scanner::new(contents.clone());
+
+        match scanner.scan_tokens() {
+            Ok(tokens) => match Parser::new(tokens.clone()).parse() {
+                Ok(stmts) => {
+                    let stmts = optimized(stmts, self.optimize);
+                    println!("{}", ast_printer::print_stmts(&stmts))
+                }
+                Err(errors) => {
+                    for err in errors {
+                        Lox::print_error(&err, &contents);
+                    }
+                    return Err(Failure::Parse);
+                }
+            },
+            Err(errors) => {
+                for err in errors {
+                    Lox::print_error(&err, &contents);
+                }
+                return Err(Failure::Scan);
+            }
+        }
+        Ok(())
     }
 
-    fn run_prompt(&mut self) -> Result<String, io::Error> {
+    // reads and runs one line at a time until the input runs out
+    // lines that leave a statement unfinished are held back until it is complete
+    // lines come from the interpreter's input so scripts calling read_line share it
+    // calling exit ends the session too, with the code it was given
+    fn run_prompt(&mut self) -> Result<(), Failure> {
+        let mut buffer = String::new();
+
         loop {
             let mut input = String::new();
 
-            print!("> ");
+            if buffer.is_empty() {
+                print!("> ");
+            } else {
+                print!("... ");
+            }
 
-            stdout().flush().unwrap();
-            stdin().read_line(&mut input).expect("Failed to read input");
+            stdout().flush()?;
+            if self.interpreter.read_line(&mut input)? == 0 {
+                // EOF, e.g. Ctrl-D or the end of piped input
+                // whatever is left over is run so its error gets reported
+                if !buffer.trim().is_empty() {
+                    Lox::exited(self.run_line(buffer.as_str()))?;
+                }
+                println!();
+                return Ok(());
+            }
 
-            self.run(input.as_str());
+            buffer.push_str(&input);
 
-            self.had_error = false;
-        }
-    }
+            if buffer.trim().is_empty() {
+                buffer.clear();
+                continue;
+            }
 
-    fn run(&mut self, source: &str) {
-        let mut scanner = Scanner::new(String::from(source));
-        let result = scanner.scan_tokens();
+            // meta-commands start with ':', which can't begin a statement
+            if let Some(command) = buffer.trim().strip_prefix(':') {
+                self.run_command(command);
+                buffer.clear();
+                continue;
+            }
 
-        match result {
-            Err(err) => {
-                self.error(err);
+            // so `var x = 1` works without its semicolon, unlike in a file
+            if let Some(completed) = Lox::with_semicolon(&buffer) {
+                Lox::exited(self.run_line(&completed))?;
+                buffer.clear();
+                continue;
             }
-            Ok(tokens) => {
-                let mut parser = Parser::new(tokens.clone());
-                let result = parser.parse();
 
-                if let Ok(stmts) = result {
-                    // println!("{:#?}", stmts);
-                    let interpret_result = self.interpreter.interpret(stmts);
+            if Lox::is_incomplete(&buffer) {
+                continue;
+            }
 
-                    if let Err(err) = interpret_result {
-                        self.runtime_error(err);
-                    }
-                } else if let Err(err) = result {
-                    self.error(err)
+            // errors have already been reported and the session carries on
+            Lox::exited(self.run_line(buffer.as_str()))?;
+            buffer.clear();
+        }
+    }
+
+    // keeps only the failure that should end the session
+    fn exited(result: Result<(), Failure>) -> Result<(), Failure> {
+        match result {
+            Err(Failure::Exit(code)) => Err(Failure::Exit(code)),
+            _ => Ok(()),
+        }
+    }
+
+    fn run_command(&mut self, command: &str) {
+        match command {
+            // a fresh interpreter, dropping every definition from the session so far
+            "reset" => self.interpreter.reset(),
+            "env" => {
+                for line in self.env_listing() {
+                    println!("{line}");
                 }
             }
+            _ => eprintln!("Unknown command ':{command}'."),
         }
     }
 
-    fn error<T>(&mut self, err: T)
-    where
-        T: Error,
-    {
-        self.report(err);
+    // the globals the session has defined, one per line
+    // the natives it started with are left out, there are too many of them and they are always there
+    fn env_listing(&self) -> Vec<String> {
+        self.interpreter
+            .globals
+            .borrow()
+            .snapshot()
+            .into_iter()
+            .filter(|(name, value)| !self.interpreter.is_builtin(name, value))
+            .map(|(name, value)| format!("{name} = {}", value.debug_string()))
+            .collect()
+    }
+
+    // true when the only problem with the source is that it ends too early,
+    // like an open brace or string, so more input could still fix it
+    fn is_incomplete(source: &str) -> bool {
+        let mut scanner = Scanner::new(String::from(source));
+        match scanner.scan_tokens() {
+            Err(errors) => errors.iter().any(|err| {
+                err.message == "Unterminated string." || err.message == "Unclosed block comment."
+            }),
+            Ok(tokens) => match Parser::new(tokens.clone()).parse_repl() {
+                Err(errors) => errors.iter().any(|err| err.token.ttype == TokenType::Eof),
+                Ok(_) => false,
+            },
+        }
+    }
+
+    // the source with a semicolon added at the end, if it's incomplete only for want of one
+    // anything else left open, like a brace, still needs more lines
+    fn with_semicolon(source: &str) -> Option<String> {
+        if !Lox::is_incomplete(source) {
+            return None;
+        }
+        let completed = format!("{};", source.trim_end());
+        let mut scanner = Scanner::new(completed.clone());
+        let tokens = scanner.scan_tokens().ok()?.clone();
+        Parser::new(tokens).parse_repl().ok().map(|_| completed)
+    }
+
+    fn run(&mut self, source: &str) -> Result<(), Failure> {
+        self.run_source(source, false)
+    }
+
+    // a line from the prompt, where a bare expression has its value printed
+    fn run_line(&mut self, source: &str) -> Result<(), Failure> {
+        self.run_source(source, true)
     }
 
-    fn report<T>(&mut self, err: T)
+    // reports every error, then fails with the stage they came from
+    fn run_source(&mut self, source: &str, repl: bool) -> Result<(), Failure> {
+        let Err(errors) = run_stages(&mut self.interpreter, source, repl, self.optimize) else {
+            return Ok(());
+        };
+
+        // exit is reported through the exit code alone
+        for err in &errors {
+            if !matches!(err, Error::Runtime(RuntimeBreak::ExitBreak(_))) {
+                Lox::print_error(err, source);
+            }
+        }
+        Err(Failure::from(&errors[0]))
+    }
+
+    // prints the message followed by the offending source line
+    fn print_error<T>(err: &T, source: &str)
     where
-        T: Error,
+        T: SourceError,
     {
         println!("{err}");
-        self.had_error = true
+        if let Some((line, column, width)) = err.span() {
+            let source = err.file_source().unwrap_or(source);
+            print!("{}", render_snippet(source, line, column, width));
+        }
     }
+}
 
-    fn runtime_error(&mut self, err: RuntimeBreak) {
-        println!("{err}");
-        if let RuntimeBreak::RuntimeErrorBreak(_re) = err {
-            self.had_runtime_error = true
-        }
+// runs a whole program on a fresh interpreter, writing anything it prints to out
+// errors are returned rather than printed, so the caller decides how to report them
+pub fn run_source(source: &str, out: Box<dyn Write>) -> Result<(), Vec<Error>> {
+    run_with(&mut Interpreter::with_output(out), source)
+}
+
+// runs a program on an interpreter the caller has set up, e.g. with host functions defined
+// globals are kept, so a later call can use what an earlier one declared
+pub fn run_with(interpreter: &mut Interpreter, source: &str) -> Result<(), Vec<Error>> {
+    run_stages(interpreter, source, false, false)
+}
+
+fn run_stages(
+    interpreter: &mut Interpreter,
+    source: &str,
+    repl: bool,
+    optimize: bool,
+) -> Result<(), Vec<Error>> {
+    let mut scanner = Scanner::new(String::from(source));
+    let tokens = scanner
+        .scan_tokens()
+        .map_err(|errors| errors.into_iter().map(Error::Scan).collect::<Vec<_>>())?;
+
+    let mut parser = Parser::new(tokens.clone());
+    let result = if repl {
+        parser.parse_repl()
+    } else {
+        parser.parse()
+    };
+    let stmts =
+        result.map_err(|errors| errors.into_iter().map(Error::Parse).collect::<Vec<_>>())?;
+
+    let stmts = optimized(stmts, optimize);
+    Resolver::new(interpreter)
+        .resolve(&stmts)
+        .map_err(|err| vec![Error::Resolve(err)])?;
+
+    let result = if repl {
+        interpreter.interpret_repl(stmts)
+    } else {
+        interpreter.interpret(stmts)
+    };
+    result.map_err(|err| vec![Error::Runtime(err)])
+}
+
+fn optimized(stmts: Vec<Stmt>, optimize: bool) -> Vec<Stmt> {
+    if optimize {
+        optimizer::optimize(stmts)
+    } else {
+        stmts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::Literal;
+
+    // a REPL session fed the given input
+    fn prompt(input: &'static str) -> Lox {
+        let interpreter = Interpreter::with_io(Box::new(input.as_bytes()), Box::new(io::sink()));
+        Lox::with_interpreter(Rc::from([]), interpreter)
+    }
+
+    #[test]
+    fn prompt_returns_on_empty_input() {
+        let mut lox = prompt("");
+
+        assert!(lox.run_prompt().is_ok());
+    }
+
+    #[test]
+    fn unfinished_input_is_incomplete() {
+        assert!(Lox::is_incomplete("fun f() {"));
+        assert!(Lox::is_incomplete("fun f() {\n  return (1 +"));
+        assert!(Lox::is_incomplete("print \"abc"));
+        assert!(Lox::is_incomplete("/* still commenting"));
+        assert!(Lox::is_incomplete("var a = 1"));
+
+        assert!(!Lox::is_incomplete("fun f() {\n  return 1;\n}"));
+        assert!(!Lox::is_incomplete("1 + 2"));
+        assert!(!Lox::is_incomplete("print );"));
+    }
+
+    #[test]
+    fn prompt_joins_lines_until_the_statement_is_complete() {
+        let mut lox = prompt("fun f() {\n  return 1;\n}\nvar a = f();\n");
+
+        assert!(lox.run_prompt().is_ok());
+        assert!(lox.run_line("a;").is_ok());
+    }
+
+    #[test]
+    fn prompt_adds_a_missing_final_semicolon() {
+        assert_eq!(
+            Lox::with_semicolon("var a = 1\n").as_deref(),
+            Some("var a = 1;")
+        );
+        assert_eq!(Lox::with_semicolon("print a").as_deref(), Some("print a;"));
+        assert_eq!(Lox::with_semicolon("var a = 1;"), None);
+        assert_eq!(Lox::with_semicolon("1 + 2"), None);
+        assert_eq!(Lox::with_semicolon("fun f() {"), None);
+        assert_eq!(Lox::with_semicolon("var a = (1 +"), None);
+        assert_eq!(Lox::with_semicolon("print \"abc"), None);
+
+        let mut lox = prompt("var a = 1\nfun f() {\n  return a;\n}\nvar b = f()\n");
+        assert!(lox.run_prompt().is_ok());
+        assert!(lox.run_line("b;").is_ok());
+    }
+
+    #[test]
+    fn prompt_skips_blank_lines() {
+        let mut lox = prompt("\n   \n\t\nvar a = 1;\n");
+
+        assert!(lox.run_prompt().is_ok());
+        assert!(lox.run_line("a;").is_ok());
+    }
+
+    #[test]
+    fn reset_forgets_everything_defined_so_far() {
+        let mut lox = prompt("var a = 1;\nfun f() {}\n:reset\nvar b = 2;\n");
+
+        assert!(lox.run_prompt().is_ok());
+        assert!(matches!(lox.run_line("a;"), Err(Failure::Runtime)));
+        assert!(matches!(lox.run_line("f;"), Err(Failure::Runtime)));
+        assert!(lox.run_line("b;").is_ok());
+        assert!(lox.run_line("clock;").is_ok());
+    }
+
+    #[test]
+    fn env_lists_the_session_globals_by_name() {
+        let mut lox = prompt("var b = \"two\";\nvar a = [1];\nfun f() {}\nclass C {}\n:env\n");
+
+        assert!(lox.run_prompt().is_ok());
+        assert_eq!(
+            lox.env_listing(),
+            ["C = C", "a = [1]", "b = \"two\"", "f = <fn f>"]
+        );
+        lox.interpreter.reset();
+        assert!(lox.env_listing().is_empty());
+    }
+
+    #[test]
+    fn env_lists_natives_the_session_defined_itself() {
+        let mut lox = prompt("var f = clock;\nvar g = 1;\nvar sqrt = 2;\n");
+        lox.interpreter
+            .define_native("host", 0, |_, _| Ok(Literal::Null));
+
+        assert!(lox.run_prompt().is_ok());
+        assert_eq!(
+            lox.env_listing(),
+            [
+                "f = <native fn>",
+                "g = 1",
+                "host = <native fn host>",
+                "sqrt = 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn exit_ends_the_session_with_its_code() {
+        let mut lox = prompt("var a = 1;\nexit(2)\nvar b = 2;\n");
+
+        assert!(matches!(lox.run_prompt(), Err(Failure::Exit(2))));
+        assert!(lox.run_line("a;").is_ok());
+        assert!(matches!(lox.run_line("b;"), Err(Failure::Runtime)));
+    }
+
+    #[test]
+    fn run_source_returns_errors_from_each_stage() {
+        let errors = run_source("print \"abc", Box::new(io::sink())).unwrap_err();
+        assert!(matches!(errors.as_slice(), [Error::Scan(_)]));
+
+        let errors = run_source("print ); print (;", Box::new(io::sink())).unwrap_err();
+        assert!(matches!(
+            errors.as_slice(),
+            [Error::Parse(_), Error::Parse(_)]
+        ));
+
+        let errors = run_source("{ var a = 1; var a = 2; }", Box::new(io::sink())).unwrap_err();
+        assert!(matches!(errors.as_slice(), [Error::Resolve(_)]));
+
+        let source = "fun f(x) { return super.m; } f(1);";
+        let errors = run_source(source, Box::new(io::sink())).unwrap_err();
+        assert!(matches!(errors.as_slice(), [Error::Resolve(_)]));
+
+        let errors = run_source("print -\"a\";", Box::new(io::sink())).unwrap_err();
+        assert!(matches!(errors.as_slice(), [Error::Runtime(_)]));
+        assert!(errors[0].to_string().contains("Operand must be number"));
+    }
+
+    #[test]
+    fn run_with_keeps_globals_between_calls() {
+        let mut interpreter = Interpreter::with_output(Box::new(io::sink()));
+
+        assert!(run_with(&mut interpreter, "var a = 1;").is_ok());
+        assert!(run_with(&mut interpreter, "a = a + 1;").is_ok());
+        assert!(run_with(&mut interpreter, "print b;").is_err());
+    }
+
+    #[test]
+    fn failed_runs_report_their_stage() {
+        let mut lox = prompt("");
+
+        assert!(matches!(lox.run("print \"abc"), Err(Failure::Scan)));
+        assert!(matches!(lox.run("print );"), Err(Failure::Parse)));
+        assert!(matches!(lox.run("return 1;"), Err(Failure::Parse)));
+        assert!(matches!(
+            lox.run("{ var a; var a; }"),
+            Err(Failure::Resolve)
+        ));
+        assert!(matches!(lox.run("print nil + 1;"), Err(Failure::Runtime)));
+        assert!(lox.run("print 1;").is_ok());
     }
 }
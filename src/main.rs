@@ -1,32 +1,31 @@
-use std::collections::HashMap;
+use lox_interpreter::interpreter::STACK_SIZE;
+use lox_interpreter::lox::{Failure, Lox};
 use std::env;
-use std::fs::File;
-use std::io::stdout;
-use std::io::{self, stdin};
 use std::process::exit;
 use std::rc::Rc;
-
-pub mod token;
-
-pub mod scanner;
-use scanner::*;
-
-pub mod error;
-use error::*;
-
-pub mod lox;
-use lox::*;
-
-pub mod interpreter;
-
-pub mod parser;
-
-pub mod environment;
-
-pub mod callable;
+use std::thread;
 
 fn main() {
-    let args: Rc<[String]> = env::args().collect();
-
-    let _int = Lox::new(args);
+    let args: Vec<String> = env::args().collect();
+
+    // deeply recursive scripts need more stack than the main thread has
+    let interpreter = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || Lox::new(Rc::from(args)).map(|_| ()))
+        .expect("Failed to start the interpreter thread");
+
+    if let Err(failure) = interpreter.join().expect("Interpreter thread panicked") {
+        // sysexits codes, as used by jlox
+        let code = match failure {
+            Failure::Usage => 64,
+            Failure::Scan | Failure::Parse | Failure::Resolve => 65,
+            Failure::Runtime => 70,
+            Failure::Exit(code) => code,
+            Failure::Io(err) => {
+                eprintln!("{err}");
+                74
+            }
+        };
+        exit(code);
+    }
 }
@@ -0,0 +1,275 @@
+use crate::interpreter::Interpreter;
+use crate::parser::*;
+use crate::token::Literal;
+
+// folds constant expressions before the program is resolved and run, e.g. 2 * 3 + 1 becomes 7
+// an operator is only folded when both of its operands are literals and applying it succeeds,
+// anything that would be a runtime error is left in place so it is still reported when it runs
+// branches that can never run, once their condition has folded to true or false, are dropped
+pub fn optimize(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts
+        .into_iter()
+        .map(optimize_stmt)
+        .filter(|stmt| *stmt != nothing())
+        .collect()
+}
+
+// what a removed statement is replaced by where a statement is still needed, like the branch of an if
+fn nothing() -> Stmt {
+    Stmt::BlockStmt(Block { statements: vec![] })
+}
+
+pub fn optimize_stmt(stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::ExprStmt(expr) => Stmt::ExprStmt(optimize_expr(expr)),
+        Stmt::PrintStmt(expr) => Stmt::PrintStmt(optimize_expr(expr)),
+        Stmt::VarDeclStmt(var) => Stmt::VarDeclStmt(optimize_var_decl(var)),
+        Stmt::MultiVarDeclStmt(vars) => {
+            Stmt::MultiVarDeclStmt(vars.into_iter().map(optimize_var_decl).collect())
+        }
+        Stmt::FuncDeclStmt(func) => Stmt::FuncDeclStmt(optimize_function(func)),
+        Stmt::ClassDeclStmt(class) => Stmt::ClassDeclStmt(ClassDecl {
+            methods: class.methods.into_iter().map(optimize_function).collect(),
+            ..class
+        }),
+        Stmt::BlockStmt(block) => Stmt::BlockStmt(Block {
+            statements: optimize(block.statements),
+        }),
+        // the branch that is kept is a statement of its own, so a block keeps its scope and nothing leaks out
+        Stmt::IfStmt(ifstmt) => match optimize_expr(ifstmt.condition) {
+            Expr::LitExpr(Literal::Bool(true)) => optimize_stmt(ifstmt.then_branch),
+            // the parser fills in a missing else branch with a nil expression statement
+            Expr::LitExpr(Literal::Bool(false))
+                if ifstmt.else_branch == Stmt::ExprStmt(Expr::LitExpr(Literal::Null)) =>
+            {
+                nothing()
+            }
+            Expr::LitExpr(Literal::Bool(false)) => optimize_stmt(ifstmt.else_branch),
+            condition => Stmt::IfStmt(Box::new(If {
+                condition,
+                then_branch: optimize_stmt(ifstmt.then_branch),
+                else_branch: optimize_stmt(ifstmt.else_branch),
+            })),
+        },
+        Stmt::WhileStmt(whilestmt) => match optimize_expr(whilestmt.condition) {
+            Expr::LitExpr(Literal::Bool(false)) => nothing(),
+            condition => Stmt::WhileStmt(Box::new(While {
+                keyword: whilestmt.keyword,
+                condition,
+                body: optimize_stmt(whilestmt.body),
+                increment: whilestmt.increment.map(optimize_expr),
+            })),
+        },
+        // the body always runs once, so even a false condition leaves the loop in place
+        Stmt::DoWhileStmt(dowhile) => Stmt::DoWhileStmt(Box::new(DoWhile {
+            keyword: dowhile.keyword,
+            body: optimize_stmt(dowhile.body),
+            condition: optimize_expr(dowhile.condition),
+        })),
+        Stmt::ForEachStmt(foreach) => Stmt::ForEachStmt(Box::new(ForEach {
+            iterable: optimize_expr(foreach.iterable),
+            body: optimize_stmt(foreach.body),
+            ..*foreach
+        })),
+        Stmt::SwitchStmt(switch) => Stmt::SwitchStmt(Box::new(Switch {
+            discriminant: optimize_expr(switch.discriminant),
+            cases: switch
+                .cases
+                .into_iter()
+                .map(|(case, statements)| (optimize_expr(case), optimize(statements)))
+                .collect(),
+            default: switch.default.map(optimize),
+        })),
+        Stmt::ReturnStmt(ret) => Stmt::ReturnStmt(Return {
+            value: optimize_expr(ret.value),
+            ..ret
+        }),
+        // for loops are desugared into while loops by the parser
+        Stmt::ForStmt(_) | Stmt::BreakStmt(_) | Stmt::ContinueStmt(_) | Stmt::ImportStmt(_) => stmt,
+    }
+}
+
+pub fn optimize_expr(expr: Expr) -> Expr {
+    match expr {
+        Expr::BinaryExpr(b) => {
+            let left = optimize_expr(b.left);
+            let right = optimize_expr(b.right);
+
+            if let (Expr::LitExpr(l), Expr::LitExpr(r)) = (&left, &right) {
+                if let Ok(value) = Interpreter::binary_op(&b.operator, l.clone(), r.clone()) {
+                    return Expr::LitExpr(value);
+                }
+            }
+            Expr::BinaryExpr(Box::new(Binary::new(left, b.operator, right)))
+        }
+        Expr::UnaryExpr(u) => {
+            let right = optimize_expr(u.right);
+
+            if let Expr::LitExpr(ref r) = right {
+                if let Ok(value) = Interpreter::unary_op(&u.operator, r.clone()) {
+                    return Expr::LitExpr(value);
+                }
+            }
+            Expr::UnaryExpr(Box::new(Unary::new(u.operator, right)))
+        }
+        // a literal needs no grouping, which lets (1 + 2) * 3 fold all the way
+        Expr::GroupingExpr(g) => match optimize_expr(g.expression) {
+            literal @ Expr::LitExpr(_) => literal,
+            expression => Expr::GroupingExpr(Box::new(Grouping::new(expression))),
+        },
+        Expr::CommaExpr(c) => Expr::CommaExpr(Box::new(Comma {
+            expressions: c.expressions.into_iter().map(optimize_expr).collect(),
+        })),
+        Expr::AssignExpr(a) => Expr::AssignExpr(Box::new(Assignment {
+            value: optimize_expr(a.value),
+            ..*a
+        })),
+        Expr::LogicExpr(l) => Expr::LogicExpr(Box::new(Logic {
+            left: optimize_expr(l.left),
+            operator: l.operator,
+            right: optimize_expr(l.right),
+        })),
+        Expr::TernaryExpr(t) => Expr::TernaryExpr(Box::new(Ternary {
+            condition: optimize_expr(t.condition),
+            then_branch: optimize_expr(t.then_branch),
+            else_branch: optimize_expr(t.else_branch),
+        })),
+        Expr::CallExpr(c) => Expr::CallExpr(Box::new(Call {
+            callee: optimize_expr(c.callee),
+            paren: c.paren,
+            arguments: c
+                .arguments
+                .map(|args| args.into_iter().map(optimize_expr).collect()),
+        })),
+        Expr::LambdaExpr(func) => Expr::LambdaExpr(Box::new(optimize_function(*func))),
+        Expr::GetExpr(g) => Expr::GetExpr(Box::new(Get {
+            object: optimize_expr(g.object),
+            name: g.name,
+        })),
+        Expr::SetExpr(s) => Expr::SetExpr(Box::new(Set {
+            object: optimize_expr(s.object),
+            name: s.name,
+            value: optimize_expr(s.value),
+        })),
+        Expr::ListExpr(list) => Expr::ListExpr(Box::new(List {
+            elements: list.elements.into_iter().map(optimize_expr).collect(),
+        })),
+        Expr::MapExpr(map) => Expr::MapExpr(Box::new(Map {
+            entries: map
+                .entries
+                .into_iter()
+                .map(|(key, value)| (optimize_expr(key), optimize_expr(value)))
+                .collect(),
+        })),
+        Expr::IndexExpr(i) => Expr::IndexExpr(Box::new(Index {
+            object: optimize_expr(i.object),
+            bracket: i.bracket,
+            index: optimize_expr(i.index),
+        })),
+        Expr::IndexSetExpr(i) => Expr::IndexSetExpr(Box::new(IndexSet {
+            object: optimize_expr(i.object),
+            bracket: i.bracket,
+            index: optimize_expr(i.index),
+            value: optimize_expr(i.value),
+        })),
+        Expr::VarExpr(_) | Expr::ThisExpr(_) | Expr::SuperExpr(_) | Expr::LitExpr(_) => expr,
+    }
+}
+
+fn optimize_var_decl(var: VarDecl) -> VarDecl {
+    VarDecl {
+        initialiser: optimize_expr(var.initialiser),
+        ..var
+    }
+}
+
+fn optimize_function(func: FuncDecl) -> FuncDecl {
+    FuncDecl {
+        params: func
+            .params
+            .into_iter()
+            .map(|param| Param {
+                default: param.default.map(optimize_expr),
+                ..param
+            })
+            .collect(),
+        body: optimize(func.body.to_vec()).into(),
+        ..func
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_printer::print_stmts;
+    use crate::scanner::Scanner;
+
+    fn optimized(source: &str) -> String {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap().clone();
+        print_stmts(&optimize(Parser::new(tokens).parse().unwrap()))
+    }
+
+    #[test]
+    fn constant_expressions_are_folded() {
+        assert_eq!(optimized("print 2 * 3 + 1;"), "(print 7)");
+        assert_eq!(optimized("print (1 + 2) * 3;"), "(print 9)");
+        assert_eq!(optimized("print \"a\" + \"b\";"), "(print \"ab\")");
+        assert_eq!(optimized("print -(2 ** 3);"), "(print -8)");
+        assert_eq!(optimized("print !nil == true;"), "(print true)");
+        assert_eq!(optimized("print 1 / 0;"), "(print Infinity)");
+        assert_eq!(
+            optimized("fun f() { return [1 + 1, \"x\" + 2]; }"),
+            "(fun f () (return (list 2 \"x2\")))"
+        );
+    }
+
+    #[test]
+    fn branches_that_never_run_are_removed() {
+        assert_eq!(optimized("if (1 < 2) print 1; else print 2;"), "(print 1)");
+        assert_eq!(optimized("if (!true) print 1; else print 2;"), "(print 2)");
+        assert_eq!(optimized("if (false) print 1; print 2;"), "(print 2)");
+        assert_eq!(
+            optimized("while (false) { print 1; } print 2;"),
+            "(print 2)"
+        );
+        assert_eq!(
+            optimized("for (var i = 0; 1 > 2; i = i + 1) print i;"),
+            "(block (var i 0))"
+        );
+        assert_eq!(
+            optimized("fun f() { if (true) return 1; return 2; }"),
+            "(fun f () (return 1) (return 2))"
+        );
+    }
+
+    #[test]
+    fn kept_branches_keep_their_scope() {
+        assert_eq!(
+            optimized("{ if (true) { var x = 1; } print x; }"),
+            "(block (block (var x 1)) (print x))"
+        );
+        assert_eq!(
+            optimized("if (false) { var x = 1; } else { var y = 2; }"),
+            "(block (var y 2))"
+        );
+    }
+
+    #[test]
+    fn conditions_that_are_not_constant_booleans_are_kept() {
+        assert_eq!(optimized("if (a) print 1;"), "(if a (print 1))");
+        assert_eq!(optimized("if (nil) print 1;"), "(if nil (print 1))");
+        assert_eq!(optimized("while (true) print 1;"), "(while true (print 1))");
+    }
+
+    #[test]
+    fn expressions_needing_runtime_values_or_errors_are_kept() {
+        assert_eq!(optimized("print a + 1 * 2;"), "(print (+ a 2))");
+        assert_eq!(optimized("print 1 + 2 + a;"), "(print (+ 3 a))");
+        assert_eq!(optimized("print a + 1 + 2;"), "(print (+ (+ a 1) 2))");
+        assert_eq!(optimized("print -\"a\";"), "(print (- \"a\"))");
+        assert_eq!(optimized("print nil + 1;"), "(print (+ nil 1))");
+        assert_eq!(optimized("print 1.5 & 1;"), "(print (& 1.5 1))");
+        assert_eq!(optimized("print (a);"), "(print (group a))");
+    }
+}
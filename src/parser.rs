@@ -4,16 +4,36 @@ use crate::token::*;
 
 use crate::error::ParseError;
 
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_EXPR_ID: AtomicUsize = AtomicUsize::new(0);
+
+// gives each expression that refers to a variable a unique id for the resolver to key on
+// ids are never reused so resolutions from earlier REPL lines stay valid
+fn next_expr_id() -> usize {
+    NEXT_EXPR_ID.fetch_add(1, Ordering::Relaxed)
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Stmt {
     ExprStmt(Expr),
+    ClassDeclStmt(ClassDecl),
     FuncDeclStmt(FuncDecl),
     PrintStmt(Expr),
     ForStmt(Box<For>),
+    ForEachStmt(Box<ForEach>),
     IfStmt(Box<If>),
     WhileStmt(Box<While>),
+    DoWhileStmt(Box<DoWhile>),
+    SwitchStmt(Box<Switch>),
     VarDeclStmt(VarDecl),
+    // var a = 1, b = 2; declares each variable in order, in the enclosing scope
+    MultiVarDeclStmt(Vec<VarDecl>),
     ReturnStmt(Return),
+    ImportStmt(Import),
+    BreakStmt(Token),
+    ContinueStmt(Token),
     BlockStmt(Block),
 }
 
@@ -22,10 +42,21 @@ pub enum Expr {
     AssignExpr(Box<Assignment>),
     BinaryExpr(Box<Binary>),
     CallExpr(Box<Call>),
+    LambdaExpr(Box<FuncDecl>),
+    GetExpr(Box<Get>),
+    SetExpr(Box<Set>),
     GroupingExpr(Box<Grouping>),
+    CommaExpr(Box<Comma>),
+    SuperExpr(Box<Super>),
+    ThisExpr(Box<This>),
     UnaryExpr(Box<Unary>),
     VarExpr(Box<Variable>),
     LogicExpr(Box<Logic>),
+    TernaryExpr(Box<Ternary>),
+    ListExpr(Box<List>),
+    MapExpr(Box<Map>),
+    IndexExpr(Box<Index>),
+    IndexSetExpr(Box<IndexSet>),
     LitExpr(Literal),
 }
 
@@ -36,6 +67,7 @@ pub struct Block {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Assignment {
+    pub id: usize,
     pub name: Token,
     pub value: Expr,
 }
@@ -48,6 +80,58 @@ pub struct Call {
     pub arguments: Option<Vec<Expr>>,
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct Get {
+    pub object: Expr,
+    pub name: Token,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Set {
+    pub object: Expr,
+    pub name: Token,
+    pub value: Expr,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct List {
+    pub elements: Vec<Expr>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Map {
+    pub entries: Vec<(Expr, Expr)>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+// bracket is used to find the location of errors related to indexing
+pub struct Index {
+    pub object: Expr,
+    pub bracket: Token,
+    pub index: Expr,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct IndexSet {
+    pub object: Expr,
+    pub bracket: Token,
+    pub index: Expr,
+    pub value: Expr,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Super {
+    pub id: usize,
+    pub keyword: Token,
+    pub method: Token,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct This {
+    pub id: usize,
+    pub keyword: Token,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Logic {
     pub left: Expr,
@@ -55,11 +139,37 @@ pub struct Logic {
     pub right: Expr,
 }
 
+#[derive(Debug, PartialEq, Clone)]
+pub struct Ternary {
+    pub condition: Expr,
+    pub then_branch: Expr,
+    pub else_branch: Expr,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct FuncDecl {
     pub name: Token,
-    pub params: Vec<Token>,
-    pub body: Vec<Stmt>,
+    pub params: Vec<Param>,
+    // fun f(a, ...rest) binds rest to a list of the arguments after the other parameters
+    pub rest: Option<Token>,
+    // shared with every Function made from this declaration, so closures and bound methods don't copy it
+    pub body: Rc<[Stmt]>,
+    // a method declared without a parameter list, run when its property is read
+    pub getter: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+// default is evaluated when a call leaves the parameter out, and can refer to the parameters before it
+pub struct Param {
+    pub name: Token,
+    pub default: Option<Expr>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClassDecl {
+    pub name: Token,
+    pub superclass: Option<Variable>,
+    pub methods: Vec<FuncDecl>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -78,9 +188,38 @@ pub struct For {
 }
 
 #[derive(Debug, PartialEq, Clone)]
+// keyword is the "in", used to find the location of errors about the iterable
+pub struct ForEach {
+    pub variable: Token,
+    pub keyword: Token,
+    pub iterable: Expr,
+    pub body: Stmt,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+// keyword is the "while", or the "for" of a desugared for loop, used to find the location of errors
 pub struct While {
+    pub keyword: Token,
     pub condition: Expr,
     pub body: Stmt,
+    // only set for desugared for loops, kept apart from the body so that continue still runs it
+    pub increment: Option<Expr>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+// like a while loop, but the condition is checked after the body so it always runs at least once
+pub struct DoWhile {
+    pub keyword: Token,
+    pub body: Stmt,
+    pub condition: Expr,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+// each case pairs the value it matches with the statements it runs
+pub struct Switch {
+    pub discriminant: Expr,
+    pub cases: Vec<(Expr, Vec<Stmt>)>,
+    pub default: Option<Vec<Stmt>>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -89,6 +228,13 @@ pub struct VarDecl {
     pub initialiser: Expr,
 }
 
+#[derive(Debug, PartialEq, Clone)]
+// path is the string token naming the file, relative to the directory of the file importing it
+pub struct Import {
+    pub keyword: Token,
+    pub path: Token,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Return {
     pub keyword: Token,
@@ -97,6 +243,7 @@ pub struct Return {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Variable {
+    pub id: usize,
     pub name: Token,
 }
 
@@ -128,6 +275,12 @@ impl Grouping {
     }
 }
 
+#[derive(Debug, PartialEq, Clone)]
+// only parsed inside parentheses, where it can't be mistaken for separating arguments or elements
+pub struct Comma {
+    pub expressions: Vec<Expr>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Unary {
     pub operator: Token,
@@ -140,30 +293,131 @@ impl Unary {
     }
 }
 
+impl Stmt {
+    // what the statement is called when tracing
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Stmt::ExprStmt(_) => "expression",
+            Stmt::ClassDeclStmt(_) => "class",
+            Stmt::FuncDeclStmt(_) => "fun",
+            Stmt::PrintStmt(_) => "print",
+            Stmt::ForStmt(_) => "for",
+            Stmt::ForEachStmt(_) => "for in",
+            Stmt::IfStmt(_) => "if",
+            Stmt::WhileStmt(_) => "while",
+            Stmt::DoWhileStmt(_) => "do while",
+            Stmt::SwitchStmt(_) => "switch",
+            Stmt::VarDeclStmt(_) | Stmt::MultiVarDeclStmt(_) => "var",
+            Stmt::ReturnStmt(_) => "return",
+            Stmt::ImportStmt(_) => "import",
+            Stmt::BreakStmt(_) => "break",
+            Stmt::ContinueStmt(_) => "continue",
+            Stmt::BlockStmt(_) => "block",
+        }
+    }
+
+    // the line of the statement's first token that is kept in the tree, None if it has none
+    pub fn line(&self) -> Option<u32> {
+        match self {
+            Stmt::ExprStmt(expr) | Stmt::PrintStmt(expr) => expr.line(),
+            Stmt::ClassDeclStmt(class) => Some(class.name.line),
+            Stmt::FuncDeclStmt(func) => Some(func.name.line),
+            Stmt::ForStmt(forstmt) => Some(forstmt.initialiser.name.line),
+            Stmt::ForEachStmt(foreach) => Some(foreach.keyword.line),
+            Stmt::IfStmt(ifstmt) => ifstmt.condition.line(),
+            Stmt::WhileStmt(whilestmt) => Some(whilestmt.keyword.line),
+            Stmt::DoWhileStmt(dowhile) => Some(dowhile.keyword.line),
+            Stmt::SwitchStmt(switch) => switch.discriminant.line(),
+            Stmt::VarDeclStmt(var) => Some(var.name.line),
+            Stmt::MultiVarDeclStmt(vars) => vars.first().map(|var| var.name.line),
+            Stmt::ReturnStmt(ret) => Some(ret.keyword.line),
+            Stmt::ImportStmt(import) => Some(import.keyword.line),
+            Stmt::BreakStmt(keyword) | Stmt::ContinueStmt(keyword) => Some(keyword.line),
+            Stmt::BlockStmt(block) => block.statements.iter().find_map(Stmt::line),
+        }
+    }
+}
+
+impl Expr {
+    // the line of the expression's first token that is kept in the tree, None for a bare literal
+    pub fn line(&self) -> Option<u32> {
+        match self {
+            Expr::AssignExpr(a) => Some(a.name.line),
+            Expr::BinaryExpr(b) => b.left.line().or(Some(b.operator.line)),
+            Expr::LogicExpr(l) => l.left.line().or(Some(l.operator.line)),
+            Expr::CallExpr(c) => c.callee.line().or(Some(c.paren.line)),
+            Expr::LambdaExpr(func) => Some(func.name.line),
+            Expr::GetExpr(g) => g.object.line().or(Some(g.name.line)),
+            Expr::SetExpr(s) => s.object.line().or(Some(s.name.line)),
+            Expr::GroupingExpr(g) => g.expression.line(),
+            Expr::CommaExpr(c) => c.expressions.iter().find_map(Expr::line),
+            Expr::SuperExpr(s) => Some(s.keyword.line),
+            Expr::ThisExpr(t) => Some(t.keyword.line),
+            Expr::UnaryExpr(u) => Some(u.operator.line),
+            Expr::VarExpr(v) => Some(v.name.line),
+            Expr::TernaryExpr(t) => t.condition.line(),
+            Expr::ListExpr(list) => list.elements.iter().find_map(Expr::line),
+            Expr::MapExpr(map) => map.entries.iter().find_map(|(key, _)| key.line()),
+            Expr::IndexExpr(i) => i.object.line().or(Some(i.bracket.line)),
+            Expr::IndexSetExpr(i) => i.object.line().or(Some(i.bracket.line)),
+            Expr::LitExpr(_) => None,
+        }
+    }
+}
+
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    // number of loops enclosing the current statement, to validate break and continue
+    loop_depth: usize,
+    // number of functions enclosing the current statement, to validate return
+    function_depth: usize,
+    // whether the innermost function is an init method, which can't return a value
+    in_initializer: bool,
+    // lets the final expression statement leave off its semicolon
+    repl: bool,
+    // errors found so far, including those reported without abandoning the statement
+    errors: Vec<ParseError>,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self {
+            tokens,
+            current: 0,
+            loop_depth: 0,
+            function_depth: 0,
+            in_initializer: false,
+            repl: false,
+            errors: vec![],
+        }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<Stmt>, ParseError> {
-        // if let Ok(expr) = self.expression() {
-        //     expr
-        // } else {
-        //     Expr::LitExpr(Literal::Null)
-        // }
-        // self.expression()
+    // parses a line typed at the prompt, where `1 + 2` is accepted without a semicolon
+    pub fn parse_repl(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
+        self.repl = true;
+        self.parse()
+    }
+
+    // on an error, skips ahead to the next statement and keeps parsing so every syntax error is reported
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
         let mut statements: Vec<Stmt> = vec![];
 
         while !self.is_at_end() {
-            statements.push(self.declaration()?);
+            match self.declaration() {
+                Ok(stmt) => statements.push(stmt),
+                Err(err) => {
+                    self.errors.push(err);
+                    self.synchronise();
+                }
+            }
         }
 
-        Ok(statements)
+        if self.errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
     }
 
     fn statement(&mut self) -> Result<Stmt, ParseError> {
@@ -171,8 +425,14 @@ impl Parser {
             self.print_statement()
         } else if self.matches(&[Return]) {
             self.return_statement()
+        } else if self.matches(&[Break, Continue]) {
+            self.loop_jump_statement()
         } else if self.matches(&[While]) {
             self.while_statement()
+        } else if self.matches(&[Do]) {
+            self.do_while_statement()
+        } else if self.matches(&[Switch]) {
+            self.switch_statement()
         } else if self.matches(&[LeftBrace]) {
             Ok(Stmt::BlockStmt(self.block()?))
         } else if self.matches(&[If]) {
@@ -196,14 +456,122 @@ impl Parser {
         Ok(Block { statements })
     }
 
+    // switchStmt → "switch" "(" expression ")" "{" switchCase* "}" ;
+    // switchCase → ( "case" expression | "default" ) ":" declaration* ;
+    fn switch_statement(&mut self) -> Result<Stmt, ParseError> {
+        self.consume(LeftParen, "Expect ( after 'switch'.".to_string())?;
+        let discriminant = self.expression()?;
+        self.consume(RightParen, "Expect ) after switch value.".to_string())?;
+        self.consume(LeftBrace, "Expect { before switch body.".to_string())?;
+
+        let mut cases: Vec<(Expr, Vec<Stmt>)> = vec![];
+        let mut default: Option<Vec<Stmt>> = None;
+
+        while !self.check(RightBrace) && !self.is_at_end() {
+            if self.matches(&[Case]) {
+                let value = self.expression()?;
+                self.consume(Colon, "Expect ':' after case value.".to_string())?;
+                cases.push((value, self.case_body()?));
+            } else if self.matches(&[Default]) {
+                let keyword = self.previous().clone();
+                if default.is_some() {
+                    return Err(self.error(
+                        keyword,
+                        "Can't have more than one default case.".to_string(),
+                    ));
+                }
+                self.consume(Colon, "Expect ':' after 'default'.".to_string())?;
+                default = Some(self.case_body()?);
+            } else {
+                let token = self.peek().clone();
+                return Err(self.error(token, "Expect 'case' or 'default'.".to_string()));
+            }
+        }
+
+        self.consume(RightBrace, "Expect } after switch body.".to_string())?;
+        Ok(Stmt::SwitchStmt(Box::new(Switch {
+            discriminant,
+            cases,
+            default,
+        })))
+    }
+
+    // a case runs until the next case, or the end of the switch
+    fn case_body(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        let mut statements: Vec<Stmt> = vec![];
+
+        while !self.check(Case)
+            && !self.check(Default)
+            && !self.check(RightBrace)
+            && !self.is_at_end()
+        {
+            statements.push(self.declaration()?);
+        }
+        Ok(statements)
+    }
+
     // whileStmt → "while" "(" expression ")" statement ;
     fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
         self.consume(LeftParen, "Expect ( after 'while'.".to_string())?;
         let condition = self.expression()?;
         self.consume(RightParen, "Expect ) after 'while'.".to_string())?;
-        let body = self.statement()?;
+        let body = self.loop_body()?;
 
-        Ok(Stmt::WhileStmt(Box::new(While { condition, body })))
+        Ok(Stmt::WhileStmt(Box::new(While {
+            keyword,
+            condition,
+            body,
+            increment: None,
+        })))
+    }
+
+    // doWhileStmt → "do" statement "while" "(" expression ")" ";" ;
+    fn do_while_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        let body = self.loop_body()?;
+        self.consume(While, "Expect 'while' after do-while body.".to_string())?;
+        self.consume(LeftParen, "Expect ( after 'while'.".to_string())?;
+        let condition = self.expression()?;
+        self.consume(RightParen, "Expect ) after 'while'.".to_string())?;
+        self.consume(
+            Semicolon,
+            "Expect ';' after do-while condition.".to_string(),
+        )?;
+
+        Ok(Stmt::DoWhileStmt(Box::new(DoWhile {
+            keyword,
+            body,
+            condition,
+        })))
+    }
+
+    fn loop_body(&mut self) -> Result<Stmt, ParseError> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
+
+    // breakStmt → "break" ";" ;
+    // continueStmt → "continue" ";" ;
+    fn loop_jump_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+
+        if self.loop_depth == 0 {
+            return Err(self.error(
+                keyword.clone(),
+                format!("Can't use '{}' outside of a loop.", keyword.lexeme),
+            ));
+        }
+
+        self.consume(Semicolon, format!("Expect ';' after '{}'", keyword.lexeme))?;
+
+        if keyword.ttype == Break {
+            Ok(Stmt::BreakStmt(keyword))
+        } else {
+            Ok(Stmt::ContinueStmt(keyword))
+        }
     }
 
     // ifStmt → "if" "(" expression ")" statement ( "else" statement )? ;
@@ -230,8 +598,13 @@ impl Parser {
 
     // convert a for statement into the equivalent while statement, adding the declaration and increment on either side
     fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
         self.consume(LeftParen, "Expect '(' after for statement".to_string())?;
 
+        if self.check(Identifier) && self.check_next(In) {
+            return self.for_each_statement();
+        }
+
         let initialiser = if self.matches(&[Semicolon]) {
             None
         } else if self.matches(&[Var]) {
@@ -254,26 +627,15 @@ impl Parser {
         };
         self.consume(RightParen, "Expect ) after for clauses".to_string())?;
 
-        let mut body = self.statement()?;
+        let body = self.loop_body()?;
 
-        // adds the increment, e.g. i++, to the end of the body so it gets evaluated
-        if let Some(inc) = increment {
-            body = Stmt::BlockStmt(Block {
-                statements: vec![body, Stmt::ExprStmt(inc)],
-            });
-        }
-
-        // wraps the body in a while statement
-        body = match condition {
-            None => Stmt::WhileStmt(Box::new(While {
-                condition: Expr::LitExpr(Literal::Bool(true)),
-                body,
-            })),
-            Some(cond) => Stmt::WhileStmt(Box::new(While {
-                condition: cond,
-                body,
-            })),
-        };
+        // wraps the body in a while statement, which runs the increment, e.g. i++, after each iteration
+        let mut body = Stmt::WhileStmt(Box::new(While {
+            keyword,
+            condition: condition.unwrap_or(Expr::LitExpr(Literal::Bool(true))),
+            body,
+            increment,
+        }));
 
         // adds the declaration, e.g. var i = 1 to before the while loop
         if let Some(init) = initialiser {
@@ -285,6 +647,23 @@ impl Parser {
         Ok(body)
     }
 
+    // forEachStmt → "for" "(" IDENTIFIER "in" expression ")" statement ;
+    fn for_each_statement(&mut self) -> Result<Stmt, ParseError> {
+        let variable = self.advance().clone();
+        let keyword = self.advance().clone();
+        let iterable = self.expression()?;
+        self.consume(RightParen, "Expect ) after for clauses".to_string())?;
+
+        let body = self.loop_body()?;
+
+        Ok(Stmt::ForEachStmt(Box::new(ForEach {
+            variable,
+            keyword,
+            iterable,
+            body,
+        })))
+    }
+
     fn print_statement(&mut self) -> Result<Stmt, ParseError> {
         let value = self.expression()?;
         self.consume(Semicolon, "Expect ';' after value".to_string())?;
@@ -293,11 +672,25 @@ impl Parser {
 
     fn return_statement(&mut self) -> Result<Stmt, ParseError> {
         let keyword = self.previous().clone();
+
+        // the rest of the statement is still parsed, so it doesn't cause errors of its own
+        if self.function_depth == 0 {
+            self.report(
+                keyword.clone(),
+                "Can't return from top-level code.".to_string(),
+            );
+        }
         let mut value = Expr::LitExpr(Literal::Null);
 
         // Check if an expression is present
         // Semicolons can't begin expressions
         if !self.check(Semicolon) {
+            if self.in_initializer {
+                self.report(
+                    keyword.clone(),
+                    "Can't return a value from an initializer.".to_string(),
+                );
+            }
             value = self.expression()?;
         }
 
@@ -307,6 +700,25 @@ impl Parser {
 
     // varDecl → "var" IDENTIFIER ( "=" expression )? ";" ;
     fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let mut declarations = vec![self.var_declarator()?];
+        while self.matches(&[Comma]) {
+            declarations.push(self.var_declarator()?);
+        }
+
+        self.consume(
+            Semicolon,
+            "Expect ; after variable declaration.".to_string(),
+        )?;
+
+        if declarations.len() == 1 {
+            Ok(Stmt::VarDeclStmt(declarations.remove(0)))
+        } else {
+            Ok(Stmt::MultiVarDeclStmt(declarations))
+        }
+    }
+
+    // declarator → IDENTIFIER ( "=" expression )? ;
+    fn var_declarator(&mut self) -> Result<VarDecl, ParseError> {
         let name = self
             .consume(Identifier, "Expect variable name".to_string())?
             .clone();
@@ -316,54 +728,173 @@ impl Parser {
             initialiser = self.expression()?;
         }
 
-        self.consume(
-            Semicolon,
-            "Expect ; after variable declaration.".to_string(),
-        )?;
+        Ok(VarDecl { name, initialiser })
+    }
+
+    // classDecl → "class" IDENTIFIER ( "<" IDENTIFIER )? "{" function* "}" ;
+    fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let name = self
+            .consume(Identifier, "Expect class name".to_string())?
+            .clone();
 
-        Ok(Stmt::VarDeclStmt(VarDecl { name, initialiser }))
+        let superclass = if self.matches(&[Less]) {
+            let name = self
+                .consume(Identifier, "Expect superclass name".to_string())?
+                .clone();
+            Some(Variable {
+                id: next_expr_id(),
+                name,
+            })
+        } else {
+            None
+        };
+        self.consume(LeftBrace, "Expect '{' before class body".to_string())?;
+
+        let mut methods: Vec<FuncDecl> = vec![];
+        while !self.check(RightBrace) && !self.is_at_end() {
+            methods.push(self.method()?);
+        }
+
+        self.consume(RightBrace, "Expect '}' after class body".to_string())?;
+
+        Ok(Stmt::ClassDeclStmt(ClassDecl {
+            name,
+            superclass,
+            methods,
+        }))
     }
 
-    fn function(&mut self, kind: std::string::String) -> Result<Stmt, ParseError> {
+    fn function(&mut self, kind: std::string::String) -> Result<FuncDecl, ParseError> {
         let name = self
             .consume(Identifier, format!("Expect {kind} name"))?
             .clone();
         self.consume(LeftParen, format!("Expect '(' after {kind} name"))?;
+        self.function_body(name, kind)
+    }
 
-        let mut parameters: Vec<Token> = vec![];
+    // method → IDENTIFIER ( "(" parameters? ")" )? block ;
+    // a method without a parameter list is a getter
+    fn method(&mut self) -> Result<FuncDecl, ParseError> {
+        let name = self
+            .consume(Identifier, "Expect method name".to_string())?
+            .clone();
+        if self.matches(&[LeftParen]) {
+            // init runs when the class is called, and always returns the new instance
+            let kind = if &*name.lexeme == "init" {
+                "initializer"
+            } else {
+                "method"
+            };
+            return self.function_body(name, kind.to_string());
+        }
+        Ok(FuncDecl {
+            name,
+            params: vec![],
+            rest: None,
+            body: self.body("getter".to_string())?,
+            getter: true,
+        })
+    }
+
+    // lambda → "fun" "(" parameters? ")" block ;
+    // lambdas are given an empty name, positioned at their 'fun' keyword
+    fn lambda(&mut self) -> Result<Expr, ParseError> {
+        let keyword = self.previous();
+        let name = Token::new(
+            Identifier,
+            std::string::String::new(),
+            Literal::Null,
+            keyword.line,
+            keyword.column,
+        );
+        self.consume(LeftParen, "Expect '(' after 'fun'".to_string())?;
+        Ok(Expr::LambdaExpr(Box::new(
+            self.function_body(name, "function".to_string())?,
+        )))
+    }
+
+    // parses the parameters and body shared by named functions, methods and lambdas
+    fn function_body(
+        &mut self,
+        name: Token,
+        kind: std::string::String,
+    ) -> Result<FuncDecl, ParseError> {
+        let mut parameters: Vec<Param> = vec![];
+        let mut rest: Option<Token> = None;
 
         if !self.check(RightParen) {
             loop {
-                parameters.push(
-                    self.consume(Identifier, "Expect identifier name".to_string())?
-                        .clone(),
-                );
-                if !self.matches(&[Comma]) {
-                    break;
-                } else if parameters.len() >= 255 {
+                // checked before each parameter so the error points at the first one over the limit
+                if parameters.len() >= 255 {
                     return Err(self.error(
                         self.peek().clone(),
                         "Can't have more than 255 parameters".to_string(),
                     ));
+                }
+                if self.matches(&[DotDotDot]) {
+                    rest = Some(
+                        self.consume(Identifier, "Expect rest parameter name".to_string())?
+                            .clone(),
+                    );
+                    if self.matches(&[Comma]) && !self.check(RightParen) {
+                        let token = self.peek().clone();
+                        return Err(self.error(token, "Rest parameter must be last.".to_string()));
+                    }
+                    break;
+                }
+                let name = self
+                    .consume(Identifier, "Expect identifier name".to_string())?
+                    .clone();
+                let default = if self.matches(&[Equal]) {
+                    Some(self.assignment()?)
+                } else if parameters.last().is_some_and(|p| p.default.is_some()) {
+                    return Err(self.error(
+                        name,
+                        "Can't have a parameter without a default after one with a default."
+                            .to_string(),
+                    ));
+                } else {
+                    None
                 };
+                parameters.push(Param { name, default });
+                // a trailing comma before the ')' is allowed
+                if !self.matches(&[Comma]) || self.check(RightParen) {
+                    break;
+                }
             }
         };
 
         self.consume(RightParen, "Expect ')' after parameters".to_string())?;
 
-        self.consume(LeftBrace, format!("Expect '{{' before {kind} body"))?;
-        let body = self.block()?.statements;
-
-        Ok(Stmt::FuncDeclStmt(FuncDecl {
-            name: name.clone(),
+        Ok(FuncDecl {
+            name,
             params: parameters,
-            body,
-        }))
+            rest,
+            body: self.body(kind)?,
+            getter: false,
+        })
+    }
+
+    fn body(&mut self, kind: std::string::String) -> Result<Rc<[Stmt]>, ParseError> {
+        self.consume(LeftBrace, format!("Expect '{{' before {kind} body"))?;
+        // loops outside the function can't be broken out of from inside it
+        let enclosing_loop_depth = self.loop_depth;
+        let enclosing_initializer = self.in_initializer;
+        self.loop_depth = 0;
+        self.in_initializer = kind == "initializer";
+        self.function_depth += 1;
+        let body = self.block();
+        self.function_depth -= 1;
+        self.in_initializer = enclosing_initializer;
+        self.loop_depth = enclosing_loop_depth;
+        Ok(body?.statements.into())
     }
 
     fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
         let value = self.expression()?;
-        self.consume(Semicolon, "Expect ';' after value".to_string())?;
+        if !(self.repl && self.is_at_end()) {
+            self.consume(Semicolon, "Expect ';' after value".to_string())?;
+        }
         Ok(Stmt::ExprStmt(value))
     }
 
@@ -372,20 +903,37 @@ impl Parser {
         self.assignment()
     }
 
-    // assignment → IDENTIFIER "=" assignment | logic_or ;
+    // assignment → ( call "." )? IDENTIFIER "=" assignment | call "[" expression "]" "=" assignment | ternary ;
     fn assignment(&mut self) -> Result<Expr, ParseError> {
         // LHS is any expression of higher precedence
         // as all LHSs of assignments are also valid expressions
-        let expr = self.or()?;
+        let expr = self.ternary()?;
 
         if self.matches(&[Equal]) {
             // recursively call the function as assignment is right-associative
             let value = self.assignment()?;
 
-            // only return an assignment if assigning to variable
+            // only return an assignment if assigning to variable or property
             if let Expr::VarExpr(var) = expr {
                 let name = var.name;
-                return Ok(Expr::AssignExpr(Box::new(Assignment { name, value })));
+                return Ok(Expr::AssignExpr(Box::new(Assignment {
+                    id: next_expr_id(),
+                    name,
+                    value,
+                })));
+            } else if let Expr::GetExpr(get) = expr {
+                return Ok(Expr::SetExpr(Box::new(Set {
+                    object: get.object,
+                    name: get.name,
+                    value,
+                })));
+            } else if let Expr::IndexExpr(index) = expr {
+                return Ok(Expr::IndexSetExpr(Box::new(IndexSet {
+                    object: index.object,
+                    bracket: index.bracket,
+                    index: index.index,
+                    value,
+                })));
             }
             Err(ParseError {
                 token: self.previous().clone(),
@@ -396,6 +944,29 @@ impl Parser {
         }
     }
 
+    // ternary → logic_or ( "?" expression ":" ternary )? ;
+    // the else branch recurses so that chained conditionals are right-associative
+    fn ternary(&mut self) -> Result<Expr, ParseError> {
+        let condition = self.or()?;
+
+        if self.matches(&[Question]) {
+            let then_branch = self.expression()?;
+            self.consume(
+                Colon,
+                "Expect ':' after then branch of conditional expression".to_string(),
+            )?;
+            let else_branch = self.ternary()?;
+
+            Ok(Expr::TernaryExpr(Box::new(Ternary {
+                condition,
+                then_branch,
+                else_branch,
+            })))
+        } else {
+            Ok(condition)
+        }
+    }
+
     // logic_or → logic_and ( "or" logic_and )* ;
     fn or(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.and()?;
@@ -415,11 +986,11 @@ impl Parser {
 
     // logic_and → equality ( "and" equality )* ;
     fn and(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.equality()?;
+        let mut expr = self.bit_or()?;
 
         while self.matches(&[And]) {
             let operator = self.previous().clone();
-            let right = self.equality()?;
+            let right = self.bit_or()?;
             expr = Expr::LogicExpr(Box::new(Logic {
                 left: expr,
                 operator,
@@ -430,22 +1001,71 @@ impl Parser {
         Ok(expr)
     }
 
-    // declaration → varDecl | statement ;
+    // declaration → classDecl | funDecl | varDecl | statement ;
     fn declaration(&mut self) -> Result<Stmt, ParseError> {
-        if self.matches(&[Fun]) {
-            self.function("function".to_string())
+        if self.matches(&[Class]) {
+            self.class_declaration()
+        } else if self.check(Fun) && self.check_next(Identifier) {
+            // otherwise 'fun' begins a lambda in an expression statement
+            self.advance();
+            Ok(Stmt::FuncDeclStmt(self.function("function".to_string())?))
         } else if self.matches(&[Var]) {
             self.var_declaration()
+        } else if self.matches(&[Import]) {
+            self.import_statement()
         } else {
-            let stmt_result = self.statement();
+            self.statement()
+        }
+    }
 
-            if stmt_result.is_ok() {
-                stmt_result
-            } else {
-                self.synchronise();
-                stmt_result
-            }
+    // importStmt → "import" STRING ";" ;
+    fn import_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous().clone();
+        let path = self
+            .consume(String, "Expect file path after 'import'.".to_string())?
+            .clone();
+        self.consume(Semicolon, "Expect ';' after import path.".to_string())?;
+        Ok(Stmt::ImportStmt(Import { keyword, path }))
+    }
+
+    // the bitwise operators bind more loosely than equality, as in C
+    // bitOr → bitXor ( "|" bitXor )* ;
+    fn bit_or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.bit_xor()?;
+
+        while self.matches(&[Pipe]) {
+            let operator = self.previous().clone();
+            let right = self.bit_xor()?;
+            expr = Expr::BinaryExpr(Box::new(Binary::new(expr, operator, right)));
         }
+
+        Ok(expr)
+    }
+
+    // bitXor → bitAnd ( "^" bitAnd )* ;
+    fn bit_xor(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.bit_and()?;
+
+        while self.matches(&[Caret]) {
+            let operator = self.previous().clone();
+            let right = self.bit_and()?;
+            expr = Expr::BinaryExpr(Box::new(Binary::new(expr, operator, right)));
+        }
+
+        Ok(expr)
+    }
+
+    // bitAnd → equality ( "&" equality )* ;
+    fn bit_and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.equality()?;
+
+        while self.matches(&[Ampersand]) {
+            let operator = self.previous().clone();
+            let right = self.equality()?;
+            expr = Expr::BinaryExpr(Box::new(Binary::new(expr, operator, right)));
+        }
+
+        Ok(expr)
     }
 
     // equality → comparison ( ( "!=" | "==" ) comparison )* ;
@@ -465,13 +1085,26 @@ impl Parser {
         Ok(expr)
     }
 
-    // comparison → term ( ( ">" | ">=" | "<" | "<=" ) term )* ;
-    // keep looping through child term expressions until no more >, >=, <, <=
+    // comparison → shift ( ( ">" | ">=" | "<" | "<=" ) shift )* ;
+    // keep looping through child shift expressions until no more >, >=, <, <=
     // otherwise similar to equality
     fn comparison(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.term()?;
+        let mut expr = self.shift()?;
 
         while self.matches(&[Greater, GreaterEqual, Less, LessEqual]) {
+            let operator = self.previous().clone();
+            let right = self.shift()?;
+            expr = Expr::BinaryExpr(Box::new(Binary::new(expr, operator, right)));
+        }
+
+        Ok(expr)
+    }
+
+    // shift → term ( ( "<<" | ">>" ) term )* ;
+    fn shift(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.term()?;
+
+        while self.matches(&[LessLess, GreaterGreater]) {
             let operator = self.previous().clone();
             let right = self.term()?;
             expr = Expr::BinaryExpr(Box::new(Binary::new(expr, operator, right)));
@@ -495,20 +1128,37 @@ impl Parser {
         Ok(expr)
     }
 
-    // factor → unary ( ( "/" | "*" ) unary )* ;
-    // keep looping through child unary expressions until no more *, /
+    // factor → power ( ( "/" | "*" | "%" ) power )* ;
+    // keep looping through child power expressions until no more *, /
     fn factor(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.unary()?;
+        let mut expr = self.power()?;
 
-        while self.matches(&[Star, Slash]) {
+        while self.matches(&[Star, Slash, Percent]) {
             let operator = self.previous().clone();
-            let right = self.unary()?;
+            let right = self.power()?;
             expr = Expr::BinaryExpr(Box::new(Binary::new(expr, operator, right)));
         }
 
         Ok(expr)
     }
 
+    // power → unary ( "**" power )? ;
+    // recursing on the right makes it right associative, so 2 ** 3 ** 2 is 2 ** 9
+    // unary binds tighter, so -2 ** 2 is (-2) ** 2
+    fn power(&mut self) -> Result<Expr, ParseError> {
+        let expr = self.unary()?;
+
+        if self.matches(&[StarStar]) {
+            let operator = self.previous().clone();
+            let right = self.power()?;
+            return Ok(Expr::BinaryExpr(Box::new(Binary::new(
+                expr, operator, right,
+            ))));
+        }
+
+        Ok(expr)
+    }
+
     // unary → ( "!" | "-" ) unary | call ;
     // if ! or -, must be unary, recursively call unary to parse operand
     // matches a primary expression followed by any number of function calls
@@ -523,13 +1173,31 @@ impl Parser {
         self.call()
     }
 
-    // call → primary ( "(" arguments? ")" )* ;
+    // call → primary ( "(" arguments? ")" | "." IDENTIFIER | "[" expression "]" )* ;
     fn call(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.primary()?;
 
-        // parse call expression with previous expression as callee
-        while self.matches(&[LeftParen]) {
-            expr = self.arguments(expr)?;
+        loop {
+            // parse call expression with previous expression as callee
+            if self.matches(&[LeftParen]) {
+                expr = self.arguments(expr)?;
+            } else if self.matches(&[Dot]) {
+                let name = self
+                    .consume(Identifier, "Expect property name after '.'".to_string())?
+                    .clone();
+                expr = Expr::GetExpr(Box::new(Get { object: expr, name }));
+            } else if self.matches(&[LeftBracket]) {
+                let bracket = self.previous().clone();
+                let index = self.expression()?;
+                self.consume(RightBracket, "Expect ']' after index".to_string())?;
+                expr = Expr::IndexExpr(Box::new(Index {
+                    object: expr,
+                    bracket,
+                    index,
+                }));
+            } else {
+                break;
+            }
         }
 
         Ok(expr)
@@ -538,66 +1206,135 @@ impl Parser {
     // arguments → expression ( "," expression )* ;
     // also handles zero-arguments case
     fn arguments(&mut self, callee: Expr) -> Result<Expr, ParseError> {
-        let mut args: Vec<Expr> = vec![];
-
-        let try_arguments = if !self.check(RightParen) {
+        let arguments = if !self.check(RightParen) {
+            let mut args: Vec<Expr> = vec![];
             loop {
+                // checked before each argument so the error points at the first one over the limit
                 if args.len() >= 255 {
-                    break Err(self.error(
+                    return Err(self.error(
                         self.peek().clone(),
                         "Can't have more than 255 arguments".to_string(),
                     ));
                 }
                 args.push(self.expression()?);
-                if !self.matches(&[Comma]) {
-                    break Ok(Some(args));
+                // a trailing comma before the ')' is allowed
+                if !self.matches(&[Comma]) || self.check(RightParen) {
+                    break Some(args);
                 }
             }
         } else {
-            Ok(None)
+            None
         };
 
-        let paren = self.consume(RightParen, "Expect ')' after arguments".to_string())?;
+        let paren = self
+            .consume(RightParen, "Expect ')' after arguments".to_string())?
+            .clone();
 
-        if let Ok(arguments) = try_arguments {
-            Ok(Expr::CallExpr(Box::new(Call {
-                callee,
-                paren: paren.clone(),
-                arguments,
-            })))
-        } else {
-            Err(try_arguments.unwrap_err())
-        }
+        Ok(Expr::CallExpr(Box::new(Call {
+            callee,
+            paren,
+            arguments,
+        })))
     }
 
     fn primary(&mut self) -> Result<Expr, ParseError> {
         if self.matches(&[False]) {
             Ok(Expr::LitExpr(Literal::Bool(false)))
         } else if self.matches(&[True]) {
-            return Ok(Expr::LitExpr(Literal::Bool(true)));
+            Ok(Expr::LitExpr(Literal::Bool(true)))
         } else if self.matches(&[Nil]) {
-            return Ok(Expr::LitExpr(Literal::Null));
+            Ok(Expr::LitExpr(Literal::Null))
         } else if self.matches(&[Number, String]) {
-            return Ok(Expr::LitExpr(self.previous().clone().literal));
+            Ok(Expr::LitExpr(self.previous().clone().literal))
+        } else if self.matches(&[Fun]) {
+            self.lambda()
+        } else if self.matches(&[LeftBracket]) {
+            self.list()
+        } else if self.matches(&[LeftBrace]) {
+            self.map()
+        } else if self.matches(&[Super]) {
+            let keyword = self.previous().clone();
+            self.consume(Dot, "Expect '.' after 'super'".to_string())?;
+            let method = self
+                .consume(Identifier, "Expect superclass method name".to_string())?
+                .clone();
+            Ok(Expr::SuperExpr(Box::new(Super {
+                id: next_expr_id(),
+                keyword,
+                method,
+            })))
+        } else if self.matches(&[This]) {
+            Ok(Expr::ThisExpr(Box::new(This {
+                id: next_expr_id(),
+                keyword: self.previous().clone(),
+            })))
         } else if self.matches(&[Identifier]) {
-            return Ok(Expr::VarExpr(Box::new(Variable {
+            Ok(Expr::VarExpr(Box::new(Variable {
+                id: next_expr_id(),
                 name: self.previous().clone(),
-            })));
+            })))
         }
         // must find a right paren or throw error
+        // a comma inside the parentheses makes it a comma expression, e.g. (a = 1, b = 2, a + b)
         else if self.matches(&[LeftParen]) {
-            let expr = self.expression()?;
+            let mut expressions = vec![self.expression()?];
+            while self.matches(&[Comma]) {
+                expressions.push(self.expression()?);
+            }
             self.consume(RightParen, "Expect ) after expression".to_string())?;
 
-            return Ok(Expr::GroupingExpr(Box::new(Grouping::new(expr))));
+            let expr = if expressions.len() == 1 {
+                expressions.remove(0)
+            } else {
+                Expr::CommaExpr(Box::new(Comma { expressions }))
+            };
+            Ok(Expr::GroupingExpr(Box::new(Grouping::new(expr))))
         } else {
-            return Err(ParseError {
+            Err(ParseError {
                 token: self.peek().clone(),
                 message: "Expect expression.".to_string(),
-            });
+            })
         }
     }
 
+    // list → "[" ( expression ( "," expression )* )? "]" ;
+    fn list(&mut self) -> Result<Expr, ParseError> {
+        let mut elements: Vec<Expr> = vec![];
+
+        if !self.check(RightBracket) {
+            loop {
+                elements.push(self.expression()?);
+                if !self.matches(&[Comma]) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(RightBracket, "Expect ']' after list elements".to_string())?;
+        Ok(Expr::ListExpr(Box::new(List { elements })))
+    }
+
+    // map → "{" ( expression ":" expression ( "," expression ":" expression )* )? "}" ;
+    // only reached in expression position, a "{" starting a statement is still a block
+    fn map(&mut self) -> Result<Expr, ParseError> {
+        let mut entries: Vec<(Expr, Expr)> = vec![];
+
+        if !self.check(RightBrace) {
+            loop {
+                let key = self.expression()?;
+                self.consume(Colon, "Expect ':' after map key".to_string())?;
+                let value = self.expression()?;
+                entries.push((key, value));
+                if !self.matches(&[Comma]) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(RightBrace, "Expect '}' after map entries".to_string())?;
+        Ok(Expr::MapExpr(Box::new(Map { entries })))
+    }
+
     // checks if current token has any of the given types before advancing
     fn matches(&mut self, ttypes: &[TokenType]) -> bool {
         for tt in ttypes.iter() {
@@ -617,9 +1354,9 @@ impl Parser {
         message: std::string::String,
     ) -> Result<&Token, ParseError> {
         if self.check(ttype) {
-            return Ok(self.advance());
+            Ok(self.advance())
         } else {
-            return Err(self.error(self.peek().clone(), message));
+            Err(self.error(self.peek().clone(), message))
         }
     }
 
@@ -631,6 +1368,13 @@ impl Parser {
         self.peek().ttype == ttype
     }
 
+    // returns true if the token after the current one is of given type
+    fn check_next(&self, ttype: TokenType) -> bool {
+        self.tokens
+            .get(self.current + 1)
+            .is_some_and(|token| token.ttype == ttype)
+    }
+
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -654,6 +1398,11 @@ impl Parser {
         ParseError { token, message }
     }
 
+    // for errors that leave the syntax intact, where parsing can carry on without synchronising
+    fn report(&mut self, token: Token, message: std::string::String) {
+        self.errors.push(ParseError { token, message });
+    }
+
     // discard tokens until at the beginning of the next statement
     fn synchronise(&mut self) {
         self.advance();
@@ -670,8 +1419,11 @@ impl Parser {
                 TokenType::For => break,
                 TokenType::If => break,
                 TokenType::While => break,
+                TokenType::Do => break,
+                TokenType::Switch => break,
                 TokenType::Print => break,
                 TokenType::Return => break,
+                TokenType::Import => break,
                 _ => (),
             }
 
@@ -679,3 +1431,272 @@ impl Parser {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::Scanner;
+
+    fn parse(source: &str) -> Result<Vec<Stmt>, Vec<ParseError>> {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap().clone();
+        Parser::new(tokens).parse()
+    }
+
+    fn parse_repl(source: &str) -> Result<Vec<Stmt>, Vec<ParseError>> {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap().clone();
+        Parser::new(tokens).parse_repl()
+    }
+
+    // a comma separated list of n distinct names, e.g. "a0, a1, a2"
+    fn names(n: usize) -> std::string::String {
+        (0..n)
+            .map(|i| format!("a{i}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    #[test]
+    fn more_than_255_parameters_is_an_error() {
+        assert!(parse(&format!("fun f({}) {{}}", names(255))).is_ok());
+
+        let errors = parse(&format!("fun f({}) {{}}", names(256))).unwrap_err();
+        assert_eq!(errors[0].message, "Can't have more than 255 parameters");
+        assert_eq!(&*errors[0].token.lexeme, "a255");
+    }
+
+    #[test]
+    fn more_than_255_arguments_is_an_error() {
+        assert!(parse(&format!("f({});", names(255))).is_ok());
+
+        let errors = parse(&format!("f({});", names(256))).unwrap_err();
+        assert_eq!(errors[0].message, "Can't have more than 255 arguments");
+        assert_eq!(&*errors[0].token.lexeme, "a255");
+    }
+
+    #[test]
+    fn trailing_commas_are_allowed_in_calls_and_declarations() {
+        let stmts = parse("fun g(a, b,) {} g(1, 2,); var h = fun (x,) {};").unwrap();
+
+        let Stmt::FuncDeclStmt(ref g) = stmts[0] else {
+            panic!("expected a function, got {:?}", stmts[0]);
+        };
+        assert_eq!(g.params.len(), 2);
+        assert!(g.params.iter().all(|p| p.default.is_none()));
+        let Stmt::ExprStmt(Expr::CallExpr(ref call)) = stmts[1] else {
+            panic!("expected a call, got {:?}", stmts[1]);
+        };
+        assert_eq!(call.arguments.as_ref().map(Vec::len), Some(2));
+
+        assert!(parse(&format!("f({},);", names(255))).is_ok());
+        assert!(parse(&format!("fun f({},) {{}}", names(255))).is_ok());
+        assert!(parse("f(,);").is_err());
+        assert!(parse("fun f(,) {}").is_err());
+        assert!(parse("f(1,,);").is_err());
+    }
+
+    #[test]
+    fn parameters_with_defaults_come_last() {
+        let stmts = parse("fun f(a, b = 1, c = a + b) {}").unwrap();
+        let Stmt::FuncDeclStmt(ref f) = stmts[0] else {
+            panic!("expected a function, got {:?}", stmts[0]);
+        };
+        let defaults: Vec<bool> = f.params.iter().map(|p| p.default.is_some()).collect();
+        assert_eq!(defaults, [false, true, true]);
+
+        let errors = parse("fun f(a = 1, b) {}").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "Can't have a parameter without a default after one with a default."
+        );
+        assert_eq!(&*errors[0].token.lexeme, "b");
+    }
+
+    #[test]
+    fn rest_parameter_must_be_last() {
+        let stmts = parse("fun f(a, ...rest,) {}").unwrap();
+        let Stmt::FuncDeclStmt(ref f) = stmts[0] else {
+            panic!("expected a function, got {:?}", stmts[0]);
+        };
+        assert_eq!(f.params.len(), 1);
+        assert_eq!(f.rest.as_ref().map(|t| t.lexeme.as_ref()), Some("rest"));
+
+        for source in ["fun f(...rest, a) {}", "fun f(...a, ...b) {}"] {
+            let errors = parse(source).unwrap_err();
+            assert_eq!(
+                errors[0].message, "Rest parameter must be last.",
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn do_while_needs_its_condition() {
+        assert!(parse("do { break; } while (true);").is_ok());
+
+        let errors = parse("do print 1;").unwrap_err();
+        assert_eq!(errors[0].message, "Expect 'while' after do-while body.");
+        let errors = parse("do print 1; while (true)").unwrap_err();
+        assert_eq!(errors[0].message, "Expect ';' after do-while condition.");
+    }
+
+    #[test]
+    fn repl_allows_a_trailing_expression_without_semicolon() {
+        let stmts = parse_repl("var a = 1; a + 2").unwrap();
+
+        assert_eq!(stmts.len(), 2);
+        assert!(matches!(stmts[1], Stmt::ExprStmt(_)));
+        assert!(parse("a + 2").is_err());
+        assert!(parse_repl("a + 2 print a;").is_err());
+    }
+
+    #[test]
+    fn return_is_only_allowed_inside_functions() {
+        let errors = parse("return 5;").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Can't return from top-level code.");
+        assert_eq!(errors[0].token.ttype, Return);
+
+        let errors = parse("if (true) { return; }").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Can't return from top-level code.");
+
+        // the statement is parsed in full, so nothing after it is reported
+        let errors = parse("{ return 1; } print 2;").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Can't return from top-level code.");
+
+        let errors = parse("return 1; print );").unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[1].message, "Expect expression.");
+
+        assert!(parse("fun f() { if (true) { return 5; } }").is_ok());
+        assert!(parse("var f = fun () { return; };").is_ok());
+        assert!(parse("class A { m() { return this; } }").is_ok());
+    }
+
+    #[test]
+    fn statements_know_their_kind_and_line() {
+        let stmts = parse("var a = 1;\nprint\n  a + 1;\nwhile (a) {\n  a = nil;\n}\n1;").unwrap();
+        let described: Vec<(&str, Option<u32>)> = stmts
+            .iter()
+            .map(|stmt| (stmt.kind(), stmt.line()))
+            .collect();
+        assert_eq!(
+            described,
+            [
+                ("var", Some(1)),
+                ("print", Some(3)),
+                ("while", Some(4)),
+                ("expression", None)
+            ]
+        );
+    }
+
+    #[test]
+    fn initializers_can_only_return_without_a_value() {
+        let errors = parse("class A { init() { return this; } }").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "Can't return a value from an initializer."
+        );
+        assert_eq!(errors[0].token.ttype, Return);
+
+        // the rest of the class is still parsed, so its closing brace isn't reported
+        let errors = parse("class A { init() { return 1; } m() { return 2; } }").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].token.column, 20);
+
+        assert!(parse("class A { init() { if (true) return; } }").is_ok());
+        assert!(parse("class A { init() { var f = fun () { return 1; }; } }").is_ok());
+        assert!(parse("fun init() { return 1; }").is_ok());
+    }
+
+    #[test]
+    fn switch_allows_one_default_case() {
+        let stmts =
+            parse("switch (x) { case 1: print 1; print 2; case 2: default: print 3; }").unwrap();
+        let Stmt::SwitchStmt(ref switch) = stmts[0] else {
+            panic!("expected a switch, got {:?}", stmts[0]);
+        };
+        assert_eq!(switch.cases.len(), 2);
+        assert_eq!(switch.cases[0].1.len(), 2);
+        assert!(switch.cases[1].1.is_empty());
+        assert_eq!(switch.default.as_ref().map(Vec::len), Some(1));
+
+        let errors = parse("switch (x) { default: print 1; default: print 2; }").unwrap_err();
+        assert_eq!(errors[0].message, "Can't have more than one default case.");
+        assert_eq!(errors[0].token.column, 32);
+    }
+
+    #[test]
+    fn var_can_declare_several_variables() {
+        let stmts = parse("var a = 1, b, c = a;").unwrap();
+        let Stmt::MultiVarDeclStmt(ref vars) = stmts[0] else {
+            panic!("expected several declarations, got {:?}", stmts[0]);
+        };
+        let names: Vec<&str> = vars.iter().map(|v| v.name.lexeme.as_ref()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert_eq!(vars[1].initialiser, Expr::LitExpr(Literal::Null));
+
+        assert!(matches!(
+            parse("var a = 1;").unwrap()[0],
+            Stmt::VarDeclStmt(_)
+        ));
+        assert_eq!(
+            parse("var a = 1, b = 2").unwrap_err()[0].message,
+            "Expect ; after variable declaration."
+        );
+        assert_eq!(
+            parse("var a = 1, ;").unwrap_err()[0].message,
+            "Expect variable name"
+        );
+    }
+
+    #[test]
+    fn every_independent_syntax_error_is_reported() {
+        let errors = parse("var = 1;\nprint 2;\nprint (3;\nprint 4;").unwrap_err();
+
+        let reported: Vec<(u32, &str)> = errors
+            .iter()
+            .map(|e| (e.token.line, e.message.as_str()))
+            .collect();
+        assert_eq!(
+            reported,
+            [
+                (1, "Expect variable name"),
+                (3, "Expect ) after expression")
+            ]
+        );
+    }
+
+    #[test]
+    fn commas_only_make_a_comma_expression_inside_parentheses() {
+        let stmts = parse("f(a, b); f((a, b)); (a, b, c);").unwrap();
+
+        let Stmt::ExprStmt(Expr::CallExpr(ref call)) = stmts[0] else {
+            panic!("expected a call, got {:?}", stmts[0]);
+        };
+        assert_eq!(call.arguments.as_ref().map(Vec::len), Some(2));
+
+        let Stmt::ExprStmt(Expr::CallExpr(ref call)) = stmts[1] else {
+            panic!("expected a call, got {:?}", stmts[1]);
+        };
+        let arguments = call.arguments.as_ref().unwrap();
+        assert_eq!(arguments.len(), 1);
+        let Expr::GroupingExpr(ref group) = arguments[0] else {
+            panic!("expected a grouping, got {:?}", arguments[0]);
+        };
+        assert!(matches!(group.expression, Expr::CommaExpr(ref c) if c.expressions.len() == 2));
+
+        let Stmt::ExprStmt(Expr::GroupingExpr(ref group)) = stmts[2] else {
+            panic!("expected a grouping, got {:?}", stmts[2]);
+        };
+        assert!(matches!(group.expression, Expr::CommaExpr(ref c) if c.expressions.len() == 3));
+
+        assert!(parse("(a, );").is_err());
+        assert!(parse("(, a);").is_err());
+    }
+}
@@ -0,0 +1,439 @@
+use crate::error::ParseError;
+use crate::interpreter::Interpreter;
+use crate::parser::*;
+use crate::token::Token;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// walks the syntax tree once before it is run, working out how many scopes
+// separate each variable reference from the scope it was declared in
+pub struct Resolver<'a> {
+    interpreter: &'a mut Interpreter,
+    // the global scope isn't tracked, anything not found here is assumed to be global
+    scopes: Vec<HashMap<Rc<str>, Local>>,
+    // the class whose methods are being resolved, to check uses of "this" and "super"
+    current_class: ClassType,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+struct Local {
+    // whether the variable has finished being initialised
+    defined: bool,
+    // its position in the scope, in the order the interpreter will define it
+    slot: usize,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(interpreter: &'a mut Interpreter) -> Self {
+        Self {
+            interpreter,
+            scopes: vec![],
+            current_class: ClassType::None,
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &[Stmt]) -> Result<(), ParseError> {
+        for stmt in statements {
+            self.resolve_stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    fn resolve_stmt(&mut self, stmt: &Stmt) -> Result<(), ParseError> {
+        match stmt {
+            Stmt::ExprStmt(expr) => self.resolve_expr(expr),
+            Stmt::PrintStmt(expr) => self.resolve_expr(expr),
+            Stmt::BlockStmt(block) => {
+                self.begin_scope();
+                let result = self.resolve(&block.statements);
+                self.end_scope();
+                result
+            }
+            Stmt::VarDeclStmt(var) => self.resolve_var_decl(var),
+            Stmt::MultiVarDeclStmt(vars) => {
+                vars.iter().try_for_each(|var| self.resolve_var_decl(var))
+            }
+            Stmt::FuncDeclStmt(func) => {
+                // defined straight away so the function can recursively refer to itself
+                self.declare(&func.name)?;
+                self.define(&func.name);
+                self.resolve_function(func)
+            }
+            Stmt::ClassDeclStmt(class) => self.resolve_class(class),
+            Stmt::IfStmt(ifstmt) => {
+                self.resolve_expr(&ifstmt.condition)?;
+                self.resolve_stmt(&ifstmt.then_branch)?;
+                self.resolve_stmt(&ifstmt.else_branch)
+            }
+            Stmt::WhileStmt(whilestmt) => {
+                self.resolve_expr(&whilestmt.condition)?;
+                self.resolve_stmt(&whilestmt.body)?;
+                if let Some(ref increment) = whilestmt.increment {
+                    self.resolve_expr(increment)?;
+                }
+                Ok(())
+            }
+            Stmt::DoWhileStmt(dowhile) => {
+                self.resolve_stmt(&dowhile.body)?;
+                self.resolve_expr(&dowhile.condition)
+            }
+            Stmt::SwitchStmt(switch) => {
+                self.resolve_expr(&switch.discriminant)?;
+                for (case, statements) in &switch.cases {
+                    self.resolve_expr(case)?;
+                    self.resolve_case(statements)?;
+                }
+                if let Some(ref statements) = switch.default {
+                    self.resolve_case(statements)?;
+                }
+                Ok(())
+            }
+            Stmt::ForEachStmt(foreach) => {
+                self.resolve_expr(&foreach.iterable)?;
+                // the loop variable gets a scope of its own, matching the environment made for each element
+                self.begin_scope();
+                self.declare(&foreach.variable)?;
+                self.define(&foreach.variable);
+                let result = self.resolve_stmt(&foreach.body);
+                self.end_scope();
+                result
+            }
+            Stmt::ReturnStmt(ret) => self.resolve_expr(&ret.value),
+            // the imported file is resolved on its own when it runs
+            Stmt::BreakStmt(_) | Stmt::ContinueStmt(_) | Stmt::ImportStmt(_) => Ok(()),
+            // for loops are desugared into while loops by the parser
+            Stmt::ForStmt(_) => Ok(()),
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) -> Result<(), ParseError> {
+        match expr {
+            Expr::VarExpr(var) => {
+                // declared in the innermost scope but not yet defined means we're inside its initialiser
+                if let Some(Local { defined: false, .. }) = self
+                    .scopes
+                    .last()
+                    .and_then(|scope| scope.get(&var.name.lexeme))
+                {
+                    return Err(ParseError {
+                        token: var.name.clone(),
+                        message: "Can't read local variable in its own initializer.".to_string(),
+                    });
+                }
+
+                self.resolve_local(var.id, &var.name);
+                Ok(())
+            }
+            Expr::AssignExpr(assignment) => {
+                self.resolve_expr(&assignment.value)?;
+                self.resolve_local(assignment.id, &assignment.name);
+                Ok(())
+            }
+            Expr::BinaryExpr(b) => {
+                self.resolve_expr(&b.left)?;
+                self.resolve_expr(&b.right)
+            }
+            Expr::LogicExpr(l) => {
+                self.resolve_expr(&l.left)?;
+                self.resolve_expr(&l.right)
+            }
+            Expr::TernaryExpr(t) => {
+                self.resolve_expr(&t.condition)?;
+                self.resolve_expr(&t.then_branch)?;
+                self.resolve_expr(&t.else_branch)
+            }
+            Expr::CallExpr(c) => {
+                self.resolve_expr(&c.callee)?;
+                if let Some(ref args) = c.arguments {
+                    for arg in args {
+                        self.resolve_expr(arg)?;
+                    }
+                }
+                Ok(())
+            }
+            Expr::LambdaExpr(func) => self.resolve_function(func),
+            Expr::GetExpr(g) => self.resolve_expr(&g.object),
+            Expr::SetExpr(s) => {
+                self.resolve_expr(&s.value)?;
+                self.resolve_expr(&s.object)
+            }
+            Expr::SuperExpr(s) => {
+                let message = match self.current_class {
+                    ClassType::None => Some("Can't use 'super' outside of a class."),
+                    ClassType::Class => Some("Can't use 'super' in a class with no superclass."),
+                    ClassType::Subclass => None,
+                };
+                if let Some(message) = message {
+                    return Err(ParseError {
+                        token: s.keyword.clone(),
+                        message: message.to_string(),
+                    });
+                }
+                self.resolve_local(s.id, &s.keyword);
+                Ok(())
+            }
+            Expr::ThisExpr(t) => {
+                if self.current_class == ClassType::None {
+                    return Err(ParseError {
+                        token: t.keyword.clone(),
+                        message: "Can't use 'this' outside of a class.".to_string(),
+                    });
+                }
+                self.resolve_local(t.id, &t.keyword);
+                Ok(())
+            }
+            Expr::CommaExpr(c) => c
+                .expressions
+                .iter()
+                .try_for_each(|expr| self.resolve_expr(expr)),
+            Expr::ListExpr(list) => list
+                .elements
+                .iter()
+                .try_for_each(|element| self.resolve_expr(element)),
+            Expr::MapExpr(map) => map.entries.iter().try_for_each(|(key, value)| {
+                self.resolve_expr(key)?;
+                self.resolve_expr(value)
+            }),
+            Expr::IndexExpr(i) => {
+                self.resolve_expr(&i.object)?;
+                self.resolve_expr(&i.index)
+            }
+            Expr::IndexSetExpr(i) => {
+                self.resolve_expr(&i.value)?;
+                self.resolve_expr(&i.object)?;
+                self.resolve_expr(&i.index)
+            }
+            Expr::GroupingExpr(g) => self.resolve_expr(&g.expression),
+            Expr::UnaryExpr(u) => self.resolve_expr(&u.right),
+            Expr::LitExpr(_) => Ok(()),
+        }
+    }
+
+    fn resolve_var_decl(&mut self, var: &VarDecl) -> Result<(), ParseError> {
+        self.declare(&var.name)?;
+        self.resolve_expr(&var.initialiser)?;
+        self.define(&var.name);
+        Ok(())
+    }
+
+    // each case runs in a scope of its own, like a block
+    fn resolve_case(&mut self, statements: &[Stmt]) -> Result<(), ParseError> {
+        self.begin_scope();
+        let result = self.resolve(statements);
+        self.end_scope();
+        result
+    }
+
+    // parameters and body share one scope, matching the environment Function::call creates
+    fn resolve_function(&mut self, func: &FuncDecl) -> Result<(), ParseError> {
+        self.begin_scope();
+        let result = func
+            .params
+            .iter()
+            .try_for_each(|param| {
+                // resolved before the parameter is declared, so a default only sees the ones before it
+                if let Some(ref default) = param.default {
+                    self.resolve_expr(default)?;
+                }
+                self.declare(&param.name)?;
+                self.define(&param.name);
+                Ok(())
+            })
+            .and_then(|()| match func.rest {
+                Some(ref rest) => {
+                    self.declare(rest)?;
+                    self.define(rest);
+                    Ok(())
+                }
+                None => Ok(()),
+            })
+            .and_then(|()| self.resolve(&func.body));
+        self.end_scope();
+        result
+    }
+
+    // mirrors the environments the interpreter creates for "super" and "this"
+    fn resolve_class(&mut self, class: &ClassDecl) -> Result<(), ParseError> {
+        self.declare(&class.name)?;
+        self.define(&class.name);
+
+        // classes can be declared inside methods, so the enclosing one is restored afterwards
+        let enclosing_class = self.current_class;
+        self.current_class = match class.superclass {
+            Some(_) => ClassType::Subclass,
+            None => ClassType::Class,
+        };
+
+        if let Some(ref superclass) = class.superclass {
+            self.resolve_local(superclass.id, &superclass.name);
+
+            self.begin_scope();
+            self.define_name("super");
+        }
+
+        self.begin_scope();
+        self.define_name("this");
+
+        let result = class
+            .methods
+            .iter()
+            .try_for_each(|method| self.resolve_function(method));
+
+        self.end_scope();
+        if class.superclass.is_some() {
+            self.end_scope();
+        }
+        self.current_class = enclosing_class;
+        result
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    // redeclaring a global is allowed, so the REPL can redefine things, but in a local scope it's most likely a mistake
+    fn declare(&mut self, name: &Token) -> Result<(), ParseError> {
+        if let Some(scope) = self.scopes.last_mut() {
+            if scope.contains_key(&name.lexeme) {
+                return Err(ParseError {
+                    token: name.clone(),
+                    message: format!("Already a variable named '{}' in this scope.", name.lexeme),
+                });
+            }
+            let slot = scope.len();
+            scope.insert(
+                name.lexeme.clone(),
+                Local {
+                    defined: false,
+                    slot,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn define(&mut self, name: &Token) {
+        self.define_name(&name.lexeme);
+    }
+
+    // "this" and "super" are defined without being declared first
+    fn define_name(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            let slot = scope.len();
+            scope
+                .entry(name.into())
+                .or_insert(Local {
+                    defined: false,
+                    slot,
+                })
+                .defined = true;
+        }
+    }
+
+    // records how many scopes out from the innermost the variable was found in, and its slot there
+    fn resolve_local(&mut self, id: usize, name: &Token) {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(local) = scope.get(&name.lexeme) {
+                self.interpreter.resolve(id, depth, local.slot);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn resolve(source: &str) -> Result<(), ParseError> {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap().clone();
+        let stmts = Parser::new(tokens).parse().unwrap();
+
+        let mut interpreter = Interpreter::new();
+        let mut resolver = Resolver::new(&mut interpreter);
+        resolver.resolve(&stmts)
+    }
+
+    #[test]
+    fn local_variable_in_own_initializer_is_an_error() {
+        let err = resolve("var a = 1; { var a = a; }").unwrap_err();
+
+        assert_eq!(&*err.token.lexeme, "a");
+        assert_eq!(
+            err.message,
+            "Can't read local variable in its own initializer."
+        );
+    }
+
+    #[test]
+    fn redeclaring_a_local_is_an_error() {
+        let err = resolve("{ var a = 1; var a = 2; }").unwrap_err();
+        assert_eq!(err.message, "Already a variable named 'a' in this scope.");
+        assert_eq!(err.token.column, 18);
+
+        let err = resolve("fun f(a, b, a) {}").unwrap_err();
+        assert_eq!(err.message, "Already a variable named 'a' in this scope.");
+
+        let err = resolve("fun f(a) { var a; }").unwrap_err();
+        assert_eq!(err.message, "Already a variable named 'a' in this scope.");
+    }
+
+    #[test]
+    fn shadowing_and_global_redeclaration_are_allowed() {
+        assert!(resolve("var a = 1; var a = 2; fun a() {}").is_ok());
+        assert!(resolve("{ var a = 1; { var a = 2; } }").is_ok());
+        assert!(resolve("for (x in []) { var x = 1; }").is_ok());
+    }
+
+    #[test]
+    fn global_variable_in_own_initializer_is_allowed() {
+        assert!(resolve("var a = 1; var a = a;").is_ok());
+    }
+
+    #[test]
+    fn this_and_super_are_only_allowed_where_they_mean_something() {
+        let err = resolve("print this;").unwrap_err();
+        assert_eq!(err.message, "Can't use 'this' outside of a class.");
+        assert_eq!(err.token.column, 7);
+
+        let err = resolve("fun f() { return this; }").unwrap_err();
+        assert_eq!(err.message, "Can't use 'this' outside of a class.");
+
+        let err = resolve("print super.x;").unwrap_err();
+        assert_eq!(err.message, "Can't use 'super' outside of a class.");
+
+        let err = resolve("fun f(x) { return super.m; }").unwrap_err();
+        assert_eq!(err.message, "Can't use 'super' outside of a class.");
+
+        let err = resolve("class A { m() { return super.m(); } }").unwrap_err();
+        assert_eq!(
+            err.message,
+            "Can't use 'super' in a class with no superclass."
+        );
+
+        // a class declared in a method doesn't inherit the enclosing class's superclass
+        let err = resolve("class A {} class B < A { m() { class C { n() { super.m(); } } } }")
+            .unwrap_err();
+        assert_eq!(
+            err.message,
+            "Can't use 'super' in a class with no superclass."
+        );
+
+        assert!(resolve("class A { m() { return fun () { return this; }; } }").is_ok());
+        assert!(resolve("class A {} class B < A { m() { return super.m; } }").is_ok());
+        assert!(resolve("class A { m() { class B {} return this; } }").is_ok());
+    }
+}
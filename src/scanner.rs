@@ -1,32 +1,52 @@
+use crate::error::LoxError;
 use crate::token::*;
-use crate::HashMap;
-use crate::LoxError;
-use crate::Rc;
+use std::collections::HashMap;
 
 pub struct Scanner {
-    source: String,
+    // collected once up front, so looking at a character doesn't re-walk the source
+    // all positions are char indices, never byte offsets, so multi-byte UTF-8 is safe
+    source: Vec<char>,
     tokens: Vec<Token>,
     start: usize,
     current: usize,
     line: u32,
+    // index of the first character on the current line, used to work out columns
+    line_start: usize,
+    // position of the token currently being scanned, as it may span several lines
+    start_line: u32,
+    start_column: u32,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Self {
         Scanner {
-            source,
+            source: source.chars().collect(),
             tokens: vec![],
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
         }
     }
 
-    pub fn scan_tokens(&mut self) -> Result<&Vec<Token>, LoxError> {
+    // keeps scanning after an error so that every error in the source is reported
+    pub fn scan_tokens(&mut self) -> Result<&Vec<Token>, Vec<LoxError>> {
+        let mut errors: Vec<LoxError> = vec![];
+
         while !self.is_at_end() {
             // beginning of next token
             self.start = self.current;
-            self.scan_token()?;
+            self.start_line = self.line;
+            self.start_column = self.column();
+            if let Err(err) = self.scan_token() {
+                errors.push(err);
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
         }
 
         self.tokens.push(Token::new(
@@ -34,6 +54,7 @@ impl Scanner {
             String::new(),
             Literal::Null,
             self.line,
+            self.column(),
         ));
 
         Ok(&self.tokens)
@@ -59,12 +80,27 @@ impl Scanner {
                 self.add_token(TokenType::RightBrace);
                 Ok(())
             }
+            '[' => {
+                self.add_token(TokenType::LeftBracket);
+                Ok(())
+            }
+            ']' => {
+                self.add_token(TokenType::RightBracket);
+                Ok(())
+            }
             ',' => {
                 self.add_token(TokenType::Comma);
                 Ok(())
             }
             '.' => {
-                self.add_token(TokenType::Dot);
+                let ttype = if self.peek() == '.' && self.peek_next() == '.' {
+                    self.advance();
+                    self.advance();
+                    TokenType::DotDotDot
+                } else {
+                    TokenType::Dot
+                };
+                self.add_token(ttype);
                 Ok(())
             }
             '-' => {
@@ -79,8 +115,38 @@ impl Scanner {
                 self.add_token(TokenType::Semicolon);
                 Ok(())
             }
+            // only adjacent stars make an exponent, so `a * *b` is still two multiplications
             '*' => {
-                self.add_token(TokenType::Star);
+                let ttype = if self.matches('*') {
+                    TokenType::StarStar
+                } else {
+                    TokenType::Star
+                };
+                self.add_token(ttype);
+                Ok(())
+            }
+            '%' => {
+                self.add_token(TokenType::Percent);
+                Ok(())
+            }
+            '&' => {
+                self.add_token(TokenType::Ampersand);
+                Ok(())
+            }
+            '|' => {
+                self.add_token(TokenType::Pipe);
+                Ok(())
+            }
+            '^' => {
+                self.add_token(TokenType::Caret);
+                Ok(())
+            }
+            '?' => {
+                self.add_token(TokenType::Question);
+                Ok(())
+            }
+            ':' => {
+                self.add_token(TokenType::Colon);
                 Ok(())
             }
             // if the next token is =, change the tokentype
@@ -103,21 +169,25 @@ impl Scanner {
                 Ok(())
             }
             '<' => {
-                let is_equals = self.matches('=');
-                self.add_token(if is_equals {
+                let ttype = if self.matches('=') {
                     TokenType::LessEqual
+                } else if self.matches('<') {
+                    TokenType::LessLess
                 } else {
                     TokenType::Less
-                });
+                };
+                self.add_token(ttype);
                 Ok(())
             }
             '>' => {
-                let is_equals = self.matches('=');
-                self.add_token(if is_equals {
+                let ttype = if self.matches('=') {
                     TokenType::GreaterEqual
+                } else if self.matches('>') {
+                    TokenType::GreaterGreater
                 } else {
                     TokenType::Greater
-                });
+                };
+                self.add_token(ttype);
                 Ok(())
             }
             // if a second / is found, consume characters until end of line is reached
@@ -130,19 +200,7 @@ impl Scanner {
                     }
                 // block comments /* */
                 } else if self.matches('*') {
-                    while self.peek() != '*' && self.peek_next() != '/' {
-                        if self.is_at_end() {
-                            return Err(LoxError {
-                                line: self.line,
-                                message: String::from("Unclosed block comment."),
-                            });
-                        } else {
-                            self.advance();
-                        }
-                    }
-                    // consume final two characters...
-                    self.advance();
-                    self.advance();
+                    self.block_comment()?;
                 } else {
                     self.add_token(TokenType::Slash);
                 }
@@ -153,7 +211,7 @@ impl Scanner {
             '\r' => Ok(()),
             '\t' => Ok(()),
             '\n' => {
-                self.line += 1;
+                self.new_line();
                 Ok(())
             }
             '"' => self.string(),
@@ -163,52 +221,90 @@ impl Scanner {
                         self.number()?;
                         Ok(())
                     }
-                } else if c.is_alphabetic() {
+                } else if c.is_alphabetic() || c == '_' {
                     {
                         self.identifier();
                         Ok(())
                     }
                 } else {
-                    Err(LoxError {
-                        line: self.line,
-                        message: String::from("Unexpected character."),
-                    })
+                    Err(self.error(String::from("Unexpected character.")))
                 }
             }
         }
     }
 
+    // block comments can be nested, so count how many are still open
+    pub fn block_comment(&mut self) -> Result<(), LoxError> {
+        let mut depth = 1;
+
+        while depth > 0 {
+            if self.is_at_end() {
+                return Err(self.error(String::from("Unclosed block comment.")));
+            }
+
+            if self.peek() == '/' && self.peek_next() == '*' {
+                self.advance();
+                self.advance();
+                depth += 1;
+            } else if self.peek() == '*' && self.peek_next() == '/' {
+                self.advance();
+                self.advance();
+                depth -= 1;
+            } else if self.advance() == '\n' {
+                self.new_line();
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn identifier(&mut self) {
         let keywords: HashMap<String, TokenType> = HashMap::from([
             (String::from("and"), TokenType::And),
+            (String::from("break"), TokenType::Break),
+            (String::from("case"), TokenType::Case),
             (String::from("class"), TokenType::Class),
+            (String::from("continue"), TokenType::Continue),
+            (String::from("default"), TokenType::Default),
+            (String::from("do"), TokenType::Do),
             (String::from("else"), TokenType::Else),
             (String::from("false"), TokenType::False),
             (String::from("for"), TokenType::For),
             (String::from("fun"), TokenType::Fun),
             (String::from("if"), TokenType::If),
+            (String::from("import"), TokenType::Import),
+            (String::from("in"), TokenType::In),
             (String::from("nil"), TokenType::Nil),
             (String::from("or"), TokenType::Or),
             (String::from("print"), TokenType::Print),
             (String::from("return"), TokenType::Return),
             (String::from("super"), TokenType::Super),
+            (String::from("switch"), TokenType::Switch),
             (String::from("this"), TokenType::This),
             (String::from("true"), TokenType::True),
             (String::from("var"), TokenType::Var),
             (String::from("while"), TokenType::While),
         ]);
 
-        while self.peek().is_alphanumeric() {
+        while self.peek().is_alphanumeric() || self.peek() == '_' {
             self.advance();
         }
 
-        let text = self.source.to_string()[self.start..self.current].to_string();
+        let text = self.lexeme();
         let ttype = keywords.get(&text).unwrap_or(&TokenType::Identifier);
 
         self.add_token(*ttype);
     }
 
     pub fn number(&mut self) -> Result<(), LoxError> {
+        if self.lexeme() == "0" {
+            match self.peek() {
+                'x' | 'X' => return self.integer(16, "hexadecimal"),
+                'b' | 'B' => return self.integer(2, "binary"),
+                _ => {}
+            }
+        }
+
         while self.peek().is_ascii_digit() {
             self.advance();
         } // check it is a valid floating point
@@ -219,54 +315,109 @@ impl Scanner {
                 self.advance();
             }
         }
+        // an exponent, e.g. 6.022e23 or 1.5E-3
+        if matches!(self.peek(), 'e' | 'E') {
+            self.advance();
+            if matches!(self.peek(), '+' | '-') {
+                self.advance();
+            }
+            if !self.peek().is_ascii_digit() {
+                return Err(self.error("Expect digits in the exponent.".to_string()));
+            }
+            while self.peek().is_ascii_digit() {
+                self.advance();
+            }
+        }
 
-        let try_num = self.source.to_string()[self.start..self.current]
-            .to_string()
-            .parse();
+        let try_num = self.lexeme().parse();
 
         if let Ok(num) = try_num {
             self.add_token_literal(TokenType::Number, Literal::Number(num));
             Ok(())
         } else {
-            Err(LoxError {
-                line: self.line,
-                message: "No number".to_string(),
-            })
+            Err(self.error("No number".to_string()))
+        }
+    }
+
+    // 0x and 0b literals, the prefix is followed by at least one digit in the radix
+    // every letter and digit after the prefix is taken as part of the literal so 0xG is one bad token
+    fn integer(&mut self, radix: u32, kind: &str) -> Result<(), LoxError> {
+        // consume the x or b
+        self.advance();
+        while self.peek().is_ascii_alphanumeric() || self.peek() == '_' {
+            self.advance();
+        }
+
+        let digits: String = self.source[self.start + 2..self.current].iter().collect();
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return Err(self.error(format!("Invalid {kind} literal.")));
+        }
+
+        // the digits are all valid, so the only way parsing fails is overflow
+        match u64::from_str_radix(&digits, radix) {
+            Ok(n) => {
+                self.add_token_literal(TokenType::Number, Literal::Number(n as f64));
+                Ok(())
+            }
+            Err(_) => Err(self.error(format!("The {kind} literal is too large."))),
         }
     }
 
     pub fn string(&mut self) -> Result<(), LoxError> {
+        let mut value = String::new();
+        // an invalid escape is reported once the whole string is consumed, so scanning resumes after it
+        let mut invalid_escape: Option<LoxError> = None;
+
         // consume characters until the final "
         while self.peek() != '"' && !self.is_at_end() {
-            if self.peek() == '\n' {
-                self.line += 1;
+            let c = self.advance();
+
+            if c == '\\' && !self.is_at_end() {
+                let escaped = self.advance();
+                match escaped {
+                    'n' => value.push('\n'),
+                    't' => value.push('\t'),
+                    'r' => value.push('\r'),
+                    '"' => value.push('"'),
+                    '\\' => value.push('\\'),
+                    '0' => value.push('\0'),
+                    _ => {
+                        // points at the backslash
+                        let column = self.column() - 2;
+                        if escaped == '\n' {
+                            self.new_line();
+                        }
+                        invalid_escape.get_or_insert(LoxError {
+                            line: self.line,
+                            column,
+                            message: format!("Invalid escape sequence '\\{escaped}'."),
+                        });
+                    }
+                }
+            } else {
+                if c == '\n' {
+                    self.new_line();
+                }
+                value.push(c);
             }
-            self.advance();
         }
         if self.is_at_end() {
-            return Err(LoxError {
-                line: self.line,
-                message: String::from("Unterminated string."),
-            });
+            return Err(self.error(String::from("Unterminated string.")));
         }
         // encapsulate the closing "
         self.advance();
 
-        // trim quotes from string value
-        let value = String::from(&self.source)[self.start + 1..self.current - 1].to_string();
+        if let Some(err) = invalid_escape {
+            return Err(err);
+        }
+
         self.add_token_literal(TokenType::String, Literal::String(value));
         Ok(())
     }
 
     // consumes character on condition
     pub fn matches(&mut self, expected: char) -> bool {
-        if self.is_at_end()
-            || self
-                .source
-                .chars()
-                .nth(self.current)
-                .is_some_and(|c| c != expected)
-        {
+        if self.is_at_end() || self.source[self.current] != expected {
             false
         } else {
             self.current += 1;
@@ -279,7 +430,7 @@ impl Scanner {
         if self.is_at_end() {
             '\0'
         } else {
-            self.source.chars().collect::<Rc<[char]>>()[self.current]
+            self.source[self.current]
         }
     }
 
@@ -289,7 +440,7 @@ impl Scanner {
         if self.current + 1 >= self.source.len() {
             '\0'
         } else {
-            self.source.chars().collect::<Rc<[char]>>()[self.current + 1]
+            self.source[self.current + 1]
         }
     }
 
@@ -300,10 +451,7 @@ impl Scanner {
 
     pub fn advance(&mut self) -> char {
         self.current += 1;
-        self.source
-            .chars()
-            .nth(self.current - 1)
-            .expect("Failed to advance while scanning")
+        self.source[self.current - 1]
     }
 
     pub fn add_token(&mut self, ttype: TokenType) {
@@ -311,8 +459,299 @@ impl Scanner {
     }
 
     pub fn add_token_literal(&mut self, ttype: TokenType, literal: Literal) {
-        let text = &self.source[self.start..self.current];
-        self.tokens
-            .push(Token::new(ttype, String::from(text), literal, self.line))
+        let text = self.lexeme();
+        self.tokens.push(Token::new(
+            ttype,
+            text,
+            literal,
+            self.start_line,
+            self.start_column,
+        ))
+    }
+
+    // call after consuming a newline character
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
+    // 1-based column of the next character to be consumed
+    fn column(&self) -> u32 {
+        (self.current - self.line_start + 1) as u32
+    }
+
+    // errors point at the start of the token being scanned
+    fn error(&self, message: String) -> LoxError {
+        LoxError {
+            line: self.start_line,
+            column: self.start_column,
+            message,
+        }
+    }
+
+    // text of the token currently being scanned
+    fn lexeme(&self) -> String {
+        self.source[self.start..self.current].iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn scan(source: &str) -> Result<Vec<Token>, Vec<LoxError>> {
+        Scanner::new(source.to_string()).scan_tokens().cloned()
+    }
+
+    #[test]
+    fn string_escape_sequences_are_processed() {
+        let tokens = scan(r#""a\nb\tc\rd\"e\\f\0""#).unwrap();
+
+        assert_eq!(
+            tokens[0].literal,
+            Literal::String("a\nb\tc\rd\"e\\f\0".to_string())
+        );
+    }
+
+    #[test]
+    fn unknown_escape_sequence_is_an_error() {
+        let err = scan("\n\"bad \\q escape\"").unwrap_err().remove(0);
+
+        assert_eq!(err.line, 2);
+        assert_eq!(err.column, 6);
+        assert_eq!(err.message, "Invalid escape sequence '\\q'.");
+    }
+
+    #[test]
+    fn bitwise_operators_are_scanned() {
+        let tokens = scan("a & b | c ^ d << e >> f <= g < h").unwrap();
+
+        let types: Vec<TokenType> = tokens
+            .iter()
+            .map(|t| t.ttype)
+            .filter(|&ttype| ttype != TokenType::Identifier && ttype != TokenType::Eof)
+            .collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Ampersand,
+                TokenType::Pipe,
+                TokenType::Caret,
+                TokenType::LessLess,
+                TokenType::GreaterGreater,
+                TokenType::LessEqual,
+                TokenType::Less,
+            ]
+        );
+    }
+
+    #[test]
+    fn only_adjacent_stars_are_an_exponent() {
+        let types = |source: &str| -> Vec<TokenType> {
+            scan(source).unwrap().iter().map(|t| t.ttype).collect()
+        };
+
+        assert_eq!(
+            types("a**b"),
+            [
+                TokenType::Identifier,
+                TokenType::StarStar,
+                TokenType::Identifier,
+                TokenType::Eof
+            ]
+        );
+        assert_eq!(
+            types("a * *b"),
+            [
+                TokenType::Identifier,
+                TokenType::Star,
+                TokenType::Star,
+                TokenType::Identifier,
+                TokenType::Eof
+            ]
+        );
+    }
+
+    #[test]
+    fn multi_line_string_counts_lines() {
+        let tokens = scan("\"one\ntwo\" x").unwrap();
+
+        assert_eq!(tokens[0].literal, Literal::String("one\ntwo".to_string()));
+        assert_eq!(tokens[1].line, 2);
+    }
+
+    #[test]
+    fn scanning_large_input_is_linear() {
+        let source = "var x = 1 + 2; // comment\n".repeat(5000);
+
+        let start = Instant::now();
+        let tokens = scan(&source).unwrap();
+        let elapsed = start.elapsed();
+
+        // 7 tokens per line plus Eof
+        assert_eq!(tokens.len(), 5000 * 7 + 1);
+        assert_eq!(tokens.last().unwrap().line, 5001);
+        // generous enough for slow machines, re-walking the source per character takes minutes
+        assert!(elapsed < Duration::from_secs(2), "took {elapsed:?}");
+    }
+
+    #[test]
+    fn multi_byte_characters_are_scanned_by_char() {
+        let tokens = scan("print \"café 🦀\"; é").unwrap();
+
+        assert_eq!(tokens[1].literal, Literal::String("café 🦀".to_string()));
+        assert_eq!(&*tokens[1].lexeme, "\"café 🦀\"");
+        assert_eq!(tokens[2].ttype, TokenType::Semicolon);
+        assert_eq!(tokens[3].ttype, TokenType::Identifier);
+        assert_eq!(&*tokens[3].lexeme, "é");
+        assert_eq!(tokens[4].ttype, TokenType::Eof);
+    }
+
+    #[test]
+    fn nested_block_comments_are_skipped() {
+        let tokens = scan("a /* outer /* inner */ still * / comment */ b").unwrap();
+
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(&*tokens[0].lexeme, "a");
+        assert_eq!(&*tokens[1].lexeme, "b");
+    }
+
+    #[test]
+    fn block_comment_running_to_end_of_file_is_an_error() {
+        let err = scan("a\n/* never\nclosed /* */\n").unwrap_err().remove(0);
+
+        assert_eq!(err.line, 2);
+        assert_eq!(err.message, "Unclosed block comment.");
+    }
+
+    #[test]
+    fn errors_after_multi_line_comments_and_strings_report_their_own_line() {
+        let err = scan("/* one\ntwo /* nested\n*/ three\n*/ a\n  @")
+            .unwrap_err()
+            .remove(0);
+        assert_eq!(err.message, "Unexpected character.");
+        assert_eq!(err.line, 5);
+
+        let err = scan("var s = \"one\ntwo\";\n// note\n@")
+            .unwrap_err()
+            .remove(0);
+        assert_eq!(err.line, 4);
+
+        let tokens = scan("/*\n\n*/ a").unwrap();
+        assert_eq!(tokens[0].line, 3);
+    }
+
+    #[test]
+    fn identifiers_can_contain_underscores() {
+        let tokens = scan("_ __init__ a_b_c foo_2 for_ for").unwrap();
+
+        let lexemes: Vec<&str> = tokens.iter().map(|t| t.lexeme.as_ref()).collect();
+        assert_eq!(
+            lexemes,
+            ["_", "__init__", "a_b_c", "foo_2", "for_", "for", ""]
+        );
+        assert!(tokens[..5].iter().all(|t| t.ttype == TokenType::Identifier));
+        assert_eq!(tokens[5].ttype, TokenType::For);
+    }
+
+    #[test]
+    fn tokens_record_their_starting_column() {
+        let tokens = scan("var x = 1;\n  print \"a\nb\" + x;").unwrap();
+
+        let positions: Vec<(u32, u32)> = tokens.iter().map(|t| (t.line, t.column)).collect();
+        assert_eq!(
+            positions,
+            [
+                (1, 1),
+                (1, 5),
+                (1, 7),
+                (1, 9),
+                (1, 10),
+                (2, 3),
+                (2, 9),
+                (3, 4),
+                (3, 6),
+                (3, 7),
+                (3, 8)
+            ]
+        );
+    }
+
+    #[test]
+    fn every_scan_error_is_reported() {
+        let errors = scan("var a = 1 @ 2;\nprint a # b;").unwrap_err();
+
+        let positions: Vec<(u32, u32)> = errors.iter().map(|e| (e.line, e.column)).collect();
+        assert_eq!(positions, [(1, 11), (2, 9)]);
+        assert!(errors.iter().all(|e| e.message == "Unexpected character."));
+    }
+
+    #[test]
+    fn hexadecimal_and_binary_literals_are_scanned() {
+        let tokens = scan("0xFF 0x1a 0XA 0b1010 0B1 0 0.5").unwrap();
+
+        let values: Vec<&Literal> = tokens[..7].iter().map(|t| &t.literal).collect();
+        assert_eq!(
+            values,
+            [
+                &Literal::Number(255.0),
+                &Literal::Number(26.0),
+                &Literal::Number(10.0),
+                &Literal::Number(10.0),
+                &Literal::Number(1.0),
+                &Literal::Number(0.0),
+                &Literal::Number(0.5),
+            ]
+        );
+        assert_eq!(&*tokens[0].lexeme, "0xFF");
+    }
+
+    #[test]
+    fn malformed_hexadecimal_and_binary_literals_are_errors() {
+        let errors = scan("0xG;\n0b102;\n0x;\n0xFFFFFFFFFFFFFFFFF;").unwrap_err();
+
+        let messages: Vec<(u32, &str)> = errors
+            .iter()
+            .map(|e| (e.line, e.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                (1, "Invalid hexadecimal literal."),
+                (2, "Invalid binary literal."),
+                (3, "Invalid hexadecimal literal."),
+                (4, "The hexadecimal literal is too large."),
+            ]
+        );
+    }
+
+    #[test]
+    fn scientific_notation_is_scanned() {
+        let tokens = scan("1e9 6.022e23 1.5e-3 2E+2 1e0").unwrap();
+
+        let values: Vec<&Literal> = tokens[..5].iter().map(|t| &t.literal).collect();
+        assert_eq!(
+            values,
+            [
+                &Literal::Number(1e9),
+                &Literal::Number(6.022e23),
+                &Literal::Number(1.5e-3),
+                &Literal::Number(200.0),
+                &Literal::Number(1.0),
+            ]
+        );
+        assert_eq!(&*tokens[2].lexeme, "1.5e-3");
+    }
+
+    #[test]
+    fn exponents_without_digits_are_errors() {
+        let errors = scan("1e;\n1e+;\n2.5E-x;").unwrap_err();
+
+        let lines: Vec<u32> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, [1, 2, 3]);
+        assert!(errors
+            .iter()
+            .all(|e| e.message == "Expect digits in the exponent."));
     }
 }
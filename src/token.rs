@@ -1,26 +1,65 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::rc::Rc;
 
 use crate::callable::*;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Literal {
     String(String),
-    Number(f32),
+    Number(f64),
     Bool(bool),
-    Func(Function),
-    NativeFunc(NativeFunction),
+    Callable(Rc<dyn Callable>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<RefCell<LoxInstance>>),
+    List(Rc<RefCell<Vec<Literal>>>),
+    Map(Rc<RefCell<HashMap<String, Literal>>>),
     Null,
 }
 
 impl Literal {
+    #[deprecated(note = "Literal implements Display, use to_string or format! instead")]
     pub fn as_string(&self) -> String {
+        self.to_string()
+    }
+
+    // what the type native reports for the value
+    pub fn type_name(&self) -> &'static str {
         match self {
-            Literal::String(s) => s.to_owned(),
-            Literal::Number(n) => n.to_string(),
-            Literal::Bool(b) => b.to_string(),
-            Literal::Func(f) => f.to_string(),
-            Literal::NativeFunc(n) => n.to_string(),
-            Literal::Null => "nil".to_string(),
+            Literal::String(_) => "string",
+            Literal::Number(_) => "number",
+            Literal::Bool(_) => "bool",
+            Literal::Callable(c) => c.type_name(),
+            Literal::Class(_) => "class",
+            Literal::Instance(_) => "instance",
+            Literal::List(_) => "list",
+            Literal::Map(_) => "map",
+            Literal::Null => "nil",
+        }
+    }
+
+    // like to_string, but with strings quoted so "nil" can be told apart from nil
+    // for output meant for debugging, such as --dump-ast and the REPL's :env
+    pub fn debug_string(&self) -> String {
+        match self {
+            Literal::String(s) => format!("\"{s}\""),
+            Literal::List(list) => {
+                let elements: Vec<String> =
+                    list.borrow().iter().map(Literal::debug_string).collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Literal::Map(map) => {
+                let map = map.borrow();
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                let entries: Vec<String> = keys
+                    .into_iter()
+                    .map(|key| format!("\"{key}\": {}", map[key].debug_string()))
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            other => other.to_string(),
         }
     }
 
@@ -34,42 +73,103 @@ impl Literal {
     }
 }
 
+impl Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Literal::String(s) => write!(f, "{s}"),
+            Literal::Number(n) => write!(f, "{}", format_number(*n)),
+            Literal::Bool(b) => write!(f, "{b}"),
+            Literal::Callable(c) => write!(f, "{c}"),
+            Literal::Class(c) => write!(f, "{c}"),
+            Literal::Instance(i) => write!(f, "{}", i.borrow()),
+            Literal::List(list) => {
+                let elements: Vec<String> = list.borrow().iter().map(Literal::to_string).collect();
+                write!(f, "[{}]", elements.join(", "))
+            }
+            Literal::Map(map) => {
+                let map = map.borrow();
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                let entries: Vec<String> = keys
+                    .into_iter()
+                    .map(|key| format!("{key}: {}", map[key]))
+                    .collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
+            Literal::Null => write!(f, "nil"),
+        }
+    }
+}
+
+// whole numbers print without a fractional part, as in jlox
+pub fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if n.fract() == 0.0 {
+        format!("{n:.0}")
+    } else {
+        n.to_string()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TokenType {
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
+    DotDotDot,
     Minus,
     Plus,
     Semicolon,
     Slash,
     Star,
+    StarStar,
+    Percent,
+    Ampersand,
+    Pipe,
+    Caret,
+    Question,
+    Colon,
     Bang,
     BangEqual,
     Equal,
     EqualEqual,
     Greater,
     GreaterEqual,
+    GreaterGreater,
     Less,
     LessEqual,
+    LessLess,
     Identifier,
     String,
     Number,
     And,
+    Break,
+    Case,
     Class,
+    Continue,
+    Default,
+    Do,
     Else,
     False,
     Fun,
     For,
     If,
+    Import,
+    In,
     Nil,
     Or,
     Print,
     Return,
     Super,
+    Switch,
     This,
     True,
     Var,
@@ -80,18 +180,21 @@ pub enum TokenType {
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub ttype: TokenType,
-    pub lexeme: String,
+    // shared, as tokens are cloned into the syntax tree, environments and errors
+    pub lexeme: Rc<str>,
     pub literal: Literal,
     pub line: u32,
+    pub column: u32,
 }
 
 impl Token {
-    pub fn new(ttype: TokenType, lexeme: String, literal: Literal, line: u32) -> Self {
+    pub fn new(ttype: TokenType, lexeme: String, literal: Literal, line: u32, column: u32) -> Self {
         Token {
             ttype,
-            lexeme,
+            lexeme: lexeme.into(),
             literal,
             line,
+            column,
         }
     }
 }
@@ -100,8 +203,65 @@ impl Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Type: {:?}, Value: {}, Literal: {:?}",
-            self.ttype, self.lexeme, self.literal
+            "[line {}, col {}] Type: {:?}, Value: {}, Literal: {:?}",
+            self.line, self.column, self.ttype, self.lexeme, self.literal
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_are_formatted_like_jlox() {
+        assert_eq!(format_number(1.0), "1");
+        assert_eq!(format_number(1.5), "1.5");
+        assert_eq!(format_number(100.0), "100");
+        assert_eq!(format_number(0.5), "0.5");
+        assert_eq!(format_number(-3.0), "-3");
+        assert_eq!(format_number(f64::INFINITY), "Infinity");
+        assert_eq!(format_number(f64::NEG_INFINITY), "-Infinity");
+        assert_eq!(format_number(f64::NAN), "NaN");
+    }
+
+    #[test]
+    fn token_display_includes_its_position() {
+        let token = Token::new(
+            TokenType::Number,
+            "1".to_string(),
+            Literal::Number(1.0),
+            3,
+            9,
+        );
+
+        assert_eq!(
+            token.to_string(),
+            "[line 3, col 9] Type: Number, Value: 1, Literal: Number(1.0)"
+        );
+    }
+
+    #[test]
+    fn display_uses_the_number_format() {
+        assert_eq!(Literal::Number(5.0).to_string(), "5");
+        assert_eq!(Literal::Number(5.5).to_string(), "5.5");
+        assert_eq!(format!("<{}>", Literal::Null), "<nil>");
+    }
+
+    #[test]
+    fn debug_string_quotes_strings() {
+        let nil = Literal::String("nil".to_string());
+        assert_eq!(nil.to_string(), Literal::Null.to_string());
+        assert_eq!(nil.debug_string(), "\"nil\"");
+        assert_eq!(Literal::Null.debug_string(), "nil");
+        assert_eq!(Literal::Number(2.0).debug_string(), "2");
+
+        let list = Literal::List(Rc::new(RefCell::new(vec![nil.clone(), Literal::Null])));
+        let map = Literal::Map(Rc::new(RefCell::new(HashMap::from([(
+            "k".to_string(),
+            list,
+        )]))));
+        assert_eq!(map.to_string(), "{k: [nil, nil]}");
+        assert_eq!(map.debug_string(), "{\"k\": [\"nil\", nil]}");
+    }
+}
@@ -0,0 +1,65 @@
+// runs every .lox program in tests/programs and compares what it prints against the companion .expected file
+// errors are appended to the output, so failing programs can be checked too
+
+use lox_interpreter::lox;
+use std::cell::RefCell;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+// lets the harness read back what the interpreter wrote
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn run(source: &str) -> String {
+    let buffer = SharedBuffer::default();
+    let result = lox::run_source(source, Box::new(buffer.clone()));
+
+    let mut output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    for err in result.err().unwrap_or_default() {
+        output.push_str(&err.to_string());
+        output.push('\n');
+    }
+    output
+}
+
+#[test]
+fn programs_print_expected_output() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut programs: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "lox"))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty(), "no programs found in {dir:?}");
+
+    // run everything before failing so one broken program doesn't hide the others
+    let mut failures: Vec<String> = vec![];
+    for program in &programs {
+        let source = fs::read_to_string(program).unwrap();
+        let expected = fs::read_to_string(program.with_extension("expected"))
+            .unwrap_or_else(|_| panic!("{program:?} has no .expected file"));
+
+        let actual = run(&source);
+        if actual != expected {
+            failures.push(format!(
+                "{}\n--- expected ---\n{expected}--- actual ---\n{actual}",
+                program.display()
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}